@@ -1,25 +1,137 @@
-use std::fs::File;
+use std::{
+	fs::File,
+	hint::black_box,
+	time::{Duration, Instant},
+};
 
-use devout::{Devout, Framerate};
+use devout::{
+	bands,
+	colorconv::{self, ColorSpace, Simd},
+	Devout, Framerate,
+};
 
 fn main() {
+	let mut args = std::env::args().skip(1);
+
+	match args.next().as_deref() {
+		// How long turning camera frames into RGB takes
+		Some("bench-colorconv") => bench_colorconv(),
+		_ => write_test_video("out.mp4"),
+	}
+}
+
+fn write_test_video(path: &str) {
 	let mut frout = Frameout::new(1280, 720);
 	let blackyuv = rgb_yuv(255, 0, 0);
 	println!("{blackyuv:?}");
 	//return;
 	frout.rect(0, 0, 1280, 720, rgb_yuv(0, 0, 0));
 
-	let file = File::create("out.mp4").unwrap();
+	let file = File::create(path).unwrap();
 	let mut dev = Devout::new(file, Framerate::Thirty);
 
+	let started = Instant::now();
 	for tick in 0..=255u8 {
 		println!("{tick}");
+		// A hard cut halfway through so the encoder does a scene change IDR
+		// and we get more than one sync sample to seek to
+		if tick == 128 {
+			frout.rect(0, 0, 1280, 720, rgb_yuv(255, 255, 255));
+		}
+
 		let yuv = rgb_yuv(tick / 2, 0, tick);
 		frout.rect(20 + tick as usize * 3, 330, 60, 60, yuv);
-		dev.frame_yuv420(1280, 720, &frout.buffer);
+		dev.frame_yuv420(1280, 720, &frout.buffer).unwrap();
 	}
 
-	dev.done();
+	let elapsed = started.elapsed();
+	let stats = dev.stats().clone();
+	dev.done().unwrap();
+
+	println!(
+		"{} frames ({} IDR) in {:.2}s, {:.1} fps wall / {:.1} fps encoding",
+		stats.frames,
+		stats.idr_frames,
+		elapsed.as_secs_f64(),
+		stats.frames as f64 / elapsed.as_secs_f64(),
+		stats.encode_fps()
+	);
+	println!(
+		"{} bytes, {:.2}s of video, {:.0} kbps",
+		stats.bytes,
+		stats.duration.as_secs_f64(),
+		stats.bitrate_kbps()
+	);
+}
+
+fn bench_colorconv() {
+	const FRAMES: u32 = 60;
+	let space = ColorSpace::BT601_LIMITED;
+
+	println!("SIMD: {}", colorconv::simd_name());
+
+	for (width, height) in [(1280, 720), (1920, 1080)] {
+		// Something that isn't flat so nothing gets lucky with the branch
+		// predictor or the clamps
+		let mut rgb = vec![0; width * height * 3];
+		for (idx, px) in rgb.chunks_exact_mut(3).enumerate() {
+			let (x, y) = (idx % width, idx / width);
+			px.copy_from_slice(&[
+				(x * 255 / width) as u8,
+				(y * 255 / height) as u8,
+				(x ^ y) as u8,
+			]);
+		}
+		let mut yuyv = vec![0; width * height * 2];
+		colorconv::rgb_to_yuyv(space, &rgb, &mut yuyv, width, height);
+
+		let time = |f: &mut dyn FnMut(&mut [u8])| -> Duration {
+			let mut out = vec![0; width * height * 3];
+			f(&mut out);
+			let started = Instant::now();
+			for _ in 0..FRAMES {
+				f(black_box(&mut out));
+			}
+			started.elapsed() / FRAMES
+		};
+
+		// The per pixel float maths we used to do
+		let float = time(&mut |out| {
+			for (group, px) in yuyv.chunks_exact(4).zip(out.chunks_exact_mut(6)) {
+				px[..3]
+					.copy_from_slice(&colorconv::yuv_to_rgb(space, group[0], group[1], group[3]));
+				px[3..]
+					.copy_from_slice(&colorconv::yuv_to_rgb(space, group[2], group[1], group[3]));
+			}
+		});
+
+		let convert = |simd: Simd, threads: usize| {
+			colorconv::set_simd(simd);
+			bands::set_max_threads(threads);
+			time(&mut |out| colorconv::yuyv_to_rgb(space, &yuyv, out, width, height))
+		};
+
+		let mut results = vec![("float", float)];
+		let levels = Simd::available();
+		for &level in &levels {
+			let name = match level {
+				Simd::None => "fixed point",
+				level => level.name(),
+			};
+			results.push((name, convert(level, 1)));
+		}
+		let best = *levels.last().unwrap();
+		results.push(("SIMD + threads", convert(best, 0)));
+
+		println!("{width}x{height} YUYV, per frame:");
+		for (name, dur) in results {
+			println!(
+				"\t{name:<16}{:>7.2}ms  {:>5.1}% of a 30fps frame",
+				dur.as_secs_f64() * 1000.0,
+				dur.as_secs_f64() * 30.0 * 100.0
+			);
+		}
+	}
 }
 
 // Stores YUV420 and manages the drawing so we only draw what's changed
@@ -63,18 +175,11 @@ impl Frameout {
 	}
 }
 
-// https://en.wikipedia.org/wiki/YUV#Y%E2%80%B2UV444_to_RGB888_conversion
 fn rgb_yuv(r: u8, g: u8, b: u8) -> Yuv<u8> {
-	let r = r as f32 / 255.0;
-	let g = g as f32 / 255.0;
-	let b = b as f32 / 255.0;
-
-	Yuv {
-		y: 0.299 * r + 0.587 * g + 0.114 * b,
-		u: -0.147 * r - 0.289 * g + 0.436 * b + 0.5,
-		v: 0.615 * r - 0.515 * g - 0.100 * b + 0.5,
-	}
-	.tou8()
+	// Limited range because that's what a decoder assumes if the stream
+	// doesn't say
+	let [y, u, v] = colorconv::rgb_to_yuv(ColorSpace::BT601_LIMITED, r, g, b);
+	Yuv { y, u, v }
 }
 
 #[derive(Debug)]
@@ -83,13 +188,3 @@ struct Yuv<T> {
 	u: T,
 	v: T,
 }
-
-impl Yuv<f32> {
-	pub fn tou8(self) -> Yuv<u8> {
-		Yuv {
-			y: (self.y * 255.0).clamp(0.0, 255.0) as u8,
-			u: (self.u * 255.0).clamp(0.0, 255.0) as u8,
-			v: (self.v * 255.0).clamp(0.0, 255.0) as u8,
-		}
-	}
-}
@@ -0,0 +1,253 @@
+//! Splitting an image into bands of rows and doing each band on its own
+//! thread. YUV to RGB in [colorconv](crate::colorconv) does this, and so can
+//! anything else that works a row at a time, through [in_bands()].
+//!
+//! The threads are started the first time they're needed and then kept
+//! around waiting for more bands, because starting new ones for every frame
+//! costs more than a lot of the work we'd give them.
+
+use std::{
+	num::NonZeroUsize,
+	panic::{self, AssertUnwindSafe},
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		mpsc::{channel, Receiver, Sender},
+		Arc, Condvar, Mutex,
+	},
+	thread,
+};
+
+static MAX_THREADS: AtomicUsize = AtomicUsize::new(0);
+static POOL: Mutex<Option<Pool>> = Mutex::new(None);
+
+/// Don't bother with threads for less than this many rows each. Handing a
+/// band to another thread costs about as much as doing a few rows.
+const MIN_ROWS_PER_BAND: usize = 64;
+
+/// The most threads [in_bands()] will use, counting the one that called it.
+/// 0, the default, is one per core.
+pub fn set_max_threads(threads: usize) {
+	MAX_THREADS.store(threads, Ordering::Relaxed);
+}
+
+/// Call `band` with every part of `out`, a few rows at a time, across threads
+/// if there's enough rows to be worth it. It gets the number of the first row
+/// in the band and the rows themselves. Doesn't return until every band's
+/// done.
+///
+/// `row_len` is how long a row of `out` is, and bands are always a multiple
+/// of `rows_together` rows, for things like pixelating that work on blocks.
+pub fn in_bands<F>(out: &mut [u8], row_len: usize, rows_together: usize, band: F)
+where
+	F: Fn(usize, &mut [u8]) + Sync,
+{
+	if row_len == 0 || out.is_empty() {
+		return;
+	}
+
+	let height = out.len() / row_len;
+	let rows_together = rows_together.max(1);
+
+	let threads = match MAX_THREADS.load(Ordering::Relaxed) {
+		0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+		n => n,
+	}
+	.min(height / MIN_ROWS_PER_BAND.max(rows_together))
+	.max(1);
+
+	if threads == 1 {
+		band(0, out);
+		return;
+	}
+
+	let rows_per_band = height.div_ceil(threads);
+	// Round up so blocks don't get split between bands
+	let rows_per_band = rows_per_band.div_ceil(rows_together) * rows_together;
+
+	let mut bands = out.chunks_mut(rows_per_band * row_len).enumerate();
+	// We do the first band ourselves instead of sitting around waiting
+	let (_, first) = bands.next().unwrap();
+
+	let latch = Latch::new(bands.len());
+	{
+		// Waits for the other bands when it's dropped, even if ours panics,
+		// so nothing they borrow goes away underneath them
+		let _wait = Wait(&latch);
+
+		let jobs = bands.map(|(idx, rows)| {
+			let (band, latch) = (&band, &latch);
+			let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || {
+				let ran = panic::catch_unwind(AssertUnwindSafe(|| band(idx * rows_per_band, rows)));
+				latch.finish(ran.is_ok());
+			});
+
+			// SAFETY: the job only lives past this function in the type
+			// system. _wait doesn't let us return until every job's finished.
+			unsafe { std::mem::transmute::<_, Job>(job) }
+		});
+		Pool::run(threads - 1, jobs);
+
+		band(0, first);
+	}
+
+	if latch.panicked() {
+		panic!("a band panicked on another thread");
+	}
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// The threads that do bands, all waiting on the same queue.
+struct Pool {
+	jobs: Sender<Job>,
+	queue: Arc<Mutex<Receiver<Job>>>,
+	threads: usize,
+}
+
+impl Pool {
+	/// Hand `jobs` to the pool, starting threads until there's at least
+	/// `threads` of them.
+	fn run<I: Iterator<Item = Job>>(threads: usize, jobs: I) {
+		let mut pool = POOL.lock().unwrap_or_else(|e| e.into_inner());
+		let pool = pool.get_or_insert_with(|| {
+			let (jobs, queue) = channel();
+			Pool {
+				jobs,
+				queue: Arc::new(Mutex::new(queue)),
+				threads: 0,
+			}
+		});
+
+		while pool.threads < threads {
+			let queue = pool.queue.clone();
+			let started = thread::Builder::new()
+				.name(String::from("devout bands"))
+				.spawn(move || worker(&queue));
+
+			// We can still get by with the threads we've got, even if that's
+			// none and we do everything ourselves below
+			if started.is_err() {
+				break;
+			}
+			pool.threads += 1;
+		}
+
+		for job in jobs {
+			if pool.threads == 0 {
+				job();
+			} else if let Err(failed) = pool.jobs.send(job) {
+				(failed.0)();
+			}
+		}
+	}
+}
+
+fn worker(queue: &Mutex<Receiver<Job>>) {
+	loop {
+		// The lock's only held while we wait for a job, not while we do it
+		let job = queue.lock().unwrap_or_else(|e| e.into_inner()).recv();
+		match job {
+			Ok(job) => job(),
+			Err(_) => return,
+		}
+	}
+}
+
+/// Counts bands as they finish so we know when they all have.
+struct Latch {
+	/// How many haven't finished, and whether any of them panicked
+	left: Mutex<(usize, bool)>,
+	done: Condvar,
+}
+
+impl Latch {
+	fn new(count: usize) -> Self {
+		Self {
+			left: Mutex::new((count, false)),
+			done: Condvar::new(),
+		}
+	}
+
+	fn finish(&self, ok: bool) {
+		let mut left = self.left.lock().unwrap_or_else(|e| e.into_inner());
+		left.0 -= 1;
+		left.1 |= !ok;
+		if left.0 == 0 {
+			self.done.notify_all();
+		}
+	}
+
+	fn wait(&self) {
+		let mut left = self.left.lock().unwrap_or_else(|e| e.into_inner());
+		while left.0 > 0 {
+			left = self.done.wait(left).unwrap_or_else(|e| e.into_inner());
+		}
+	}
+
+	fn panicked(&self) -> bool {
+		self.left.lock().unwrap_or_else(|e| e.into_inner()).1
+	}
+}
+
+struct Wait<'a>(&'a Latch);
+
+impl Drop for Wait<'_> {
+	fn drop(&mut self) {
+		self.0.wait();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// [set_max_threads()] is global, so tests that need it to stay put take
+	/// turns
+	static THREADS_LOCK: Mutex<()> = Mutex::new(());
+
+	/// Every row gets done exactly once, with the right row number, however
+	/// many threads there are.
+	#[test]
+	fn every_row_once() {
+		let _threads = THREADS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+		let (width, height) = (5, 1000);
+
+		for threads in [0, 1, 2, 3, 7, 16] {
+			set_max_threads(threads);
+
+			for rows_together in [1, 3, 64] {
+				let mut out = vec![0u8; width * height];
+				in_bands(&mut out, width, rows_together, |first, rows| {
+					assert_eq!(first % rows_together, 0);
+					// Added, so doing a row twice shows up
+					for (idx, row) in rows.chunks_exact_mut(width).enumerate() {
+						for px in row {
+							*px = px.wrapping_add((first + idx) as u8 ^ 0x5A);
+						}
+					}
+				});
+
+				let expected: Vec<u8> = (0..height).flat_map(|row| [row as u8 ^ 0x5A; 5]).collect();
+				assert!(
+					out == expected,
+					"{threads} threads, {rows_together} together"
+				);
+			}
+		}
+
+		set_max_threads(0);
+	}
+
+	#[test]
+	#[should_panic]
+	fn panics_come_back() {
+		let _threads = THREADS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+		set_max_threads(4);
+		let mut out = vec![0u8; 4 * 1000];
+		in_bands(&mut out, 4, 1, |first, _| {
+			if first > 0 {
+				panic!("not this one");
+			}
+		});
+	}
+}
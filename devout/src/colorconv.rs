@@ -0,0 +1,786 @@
+//! Converting between 24bit RGB and the YUV layouts cameras and encoders like.
+//!
+//! Everything takes a [ColorSpace] because the same bytes mean different
+//! colours depending on which matrix and range were used to make them. If you
+//! don't know, webcams are usually [ColorSpace::BT601_LIMITED] and HD video is
+//! usually [ColorSpace::BT709_LIMITED].
+//!
+//! Chroma planes are `(width + 1) / 2` wide and `(height + 1) / 2` tall so odd
+//! sizes work, and when going from RGB to 4:2:0 or 4:2:2 the chroma is the
+//! average of the pixels it covers.
+//!
+//! Going from YUV to RGB happens every frame so it's fixed point, two pixels
+//! at a time, with SSE2 or AVX2 when we have them, and split over threads by
+//! rows with [bands](crate::bands) when the image is big enough to be worth
+//! it. [yuv_to_rgb()] is the slow float version and is what the fast one is
+//! checked against.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::bands;
+
+#[cfg(target_arch = "x86_64")]
+mod x86;
+
+/// The luma coefficients. These decide how much red, green, and blue go into
+/// brightness.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Matrix {
+	/// Standard definition. Most webcams and JPEGs.
+	Bt601,
+	/// High definition.
+	Bt709,
+}
+
+impl Matrix {
+	/// (Kr, Kb). Kg is whatever's left.
+	fn coefficients(&self) -> (f32, f32) {
+		match self {
+			Matrix::Bt601 => (0.299, 0.114),
+			Matrix::Bt709 => (0.2126, 0.0722),
+		}
+	}
+}
+
+/// How much of the byte the values use.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Range {
+	/// 0 to 255 for everything. JPEG does this.
+	Full,
+	/// 16 to 235 for luma and 16 to 240 for chroma. Also called TV or studio
+	/// range, and what video almost always is.
+	Limited,
+}
+
+impl Range {
+	/// (luma offset, luma scale, chroma scale)
+	fn scale(&self) -> (f32, f32, f32) {
+		match self {
+			Range::Full => (0.0, 255.0, 255.0),
+			Range::Limited => (16.0, 219.0, 224.0),
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorSpace {
+	pub matrix: Matrix,
+	pub range: Range,
+}
+
+impl ColorSpace {
+	pub const BT601_LIMITED: ColorSpace = ColorSpace::new(Matrix::Bt601, Range::Limited);
+	pub const BT601_FULL: ColorSpace = ColorSpace::new(Matrix::Bt601, Range::Full);
+	pub const BT709_LIMITED: ColorSpace = ColorSpace::new(Matrix::Bt709, Range::Limited);
+	pub const BT709_FULL: ColorSpace = ColorSpace::new(Matrix::Bt709, Range::Full);
+
+	pub const fn new(matrix: Matrix, range: Range) -> Self {
+		Self { matrix, range }
+	}
+}
+
+impl Default for ColorSpace {
+	fn default() -> Self {
+		Self::BT601_LIMITED
+	}
+}
+
+#[inline]
+fn to_u8(v: f32) -> u8 {
+	v.round().clamp(0.0, 255.0) as u8
+}
+
+/// Convert one pixel from YUV to RGB.
+pub fn yuv_to_rgb(space: ColorSpace, y: u8, u: u8, v: u8) -> [u8; 3] {
+	let (kr, kb) = space.matrix.coefficients();
+	let kg = 1.0 - kr - kb;
+	let (yoff, yscale, cscale) = space.range.scale();
+
+	// Everything to 0..1 for luma and -0.5..0.5 for chroma
+	let y = (y as f32 - yoff) / yscale;
+	let pb = (u as f32 - 128.0) / cscale;
+	let pr = (v as f32 - 128.0) / cscale;
+
+	let r = y + 2.0 * (1.0 - kr) * pr;
+	let b = y + 2.0 * (1.0 - kb) * pb;
+	let g = (y - kr * r - kb * b) / kg;
+
+	[to_u8(r * 255.0), to_u8(g * 255.0), to_u8(b * 255.0)]
+}
+
+/// Convert one pixel from RGB to YUV.
+pub fn rgb_to_yuv(space: ColorSpace, r: u8, g: u8, b: u8) -> [u8; 3] {
+	let (kr, kb) = space.matrix.coefficients();
+	let kg = 1.0 - kr - kb;
+	let (yoff, yscale, cscale) = space.range.scale();
+
+	let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+
+	let y = kr * r + kg * g + kb * b;
+	let pb = (b - y) / (2.0 * (1.0 - kb));
+	let pr = (r - y) / (2.0 * (1.0 - kr));
+
+	[
+		to_u8(yoff + y * yscale),
+		to_u8(128.0 + pb * cscale),
+		to_u8(128.0 + pr * cscale),
+	]
+}
+
+/// Width and height of the chroma planes of a 4:2:0 image.
+pub fn chroma_size(width: usize, height: usize) -> (usize, usize) {
+	(width.div_ceil(2), height.div_ceil(2))
+}
+
+/// How many bytes a planar 4:2:0 image takes up.
+pub fn yuv420_len(width: usize, height: usize) -> usize {
+	let (cw, ch) = chroma_size(width, height);
+	width * height + cw * ch * 2
+}
+
+/// Packed 4:2:2 where the luma comes first, `Y0 U Y1 V`.
+pub fn yuyv_to_rgb(space: ColorSpace, yuyv: &[u8], rgb: &mut [u8], width: usize, height: usize) {
+	let stride = packed422_stride(width);
+	convert_rows(space, rgb, width, height, |row| Row::Packed {
+		src: &yuyv[row * stride..(row + 1) * stride],
+		chroma_first: false,
+	});
+}
+
+/// Packed 4:2:2 where the chroma comes first, `U Y0 V Y1`.
+pub fn uyvy_to_rgb(space: ColorSpace, uyvy: &[u8], rgb: &mut [u8], width: usize, height: usize) {
+	let stride = packed422_stride(width);
+	convert_rows(space, rgb, width, height, |row| Row::Packed {
+		src: &uyvy[row * stride..(row + 1) * stride],
+		chroma_first: true,
+	});
+}
+
+/// How many bytes packed 4:2:2, YUYV or UYVY, takes up.
+pub fn packed422_len(width: usize, height: usize) -> usize {
+	packed422_stride(width) * height
+}
+
+/// Rows are padded out to a whole number of pairs
+fn packed422_stride(width: usize) -> usize {
+	width.div_ceil(2) * 4
+}
+
+/// A plane of luma and then one plane of interleaved `U V` at half size.
+pub fn nv12_to_rgb(space: ColorSpace, nv12: &[u8], rgb: &mut [u8], width: usize, height: usize) {
+	let (cw, _) = chroma_size(width, height);
+	let (luma, chroma) = nv12.split_at(width * height);
+
+	convert_rows(space, rgb, width, height, |row| {
+		let crow = row / 2;
+		Row::SemiPlanar {
+			y: &luma[row * width..(row + 1) * width],
+			uv: &chroma[crow * cw * 2..(crow + 1) * cw * 2],
+		}
+	});
+}
+
+/// Planar 4:2:0, all of the Y then all of the U and then all of the V. Also
+/// called YUV420p. This is what [crate::Devout::frame_yuv420()] takes.
+pub fn i420_to_rgb(space: ColorSpace, i420: &[u8], rgb: &mut [u8], width: usize, height: usize) {
+	let (cw, ch) = chroma_size(width, height);
+	let (luma, chroma) = i420.split_at(width * height);
+	let (uplane, vplane) = chroma.split_at(cw * ch);
+
+	convert_rows(space, rgb, width, height, |row| {
+		let crow = row / 2;
+		Row::Planar {
+			y: &luma[row * width..(row + 1) * width],
+			u: &uplane[crow * cw..(crow + 1) * cw],
+			v: &vplane[crow * cw..(crow + 1) * cw],
+		}
+	});
+}
+
+/// The most SIMD YUV to RGB will use. It's the best the machine has by
+/// default, this is here so the levels can be compared with each other and
+/// the plain integer version. Asking for more than the machine has gets what
+/// it has.
+pub fn set_simd(most: Simd) {
+	SIMD.store(most as u8, Ordering::Relaxed);
+}
+
+/// Which instruction set YUV to RGB is going to use on this machine.
+pub fn simd_name() -> &'static str {
+	Simd::current().name()
+}
+
+static SIMD: AtomicU8 = AtomicU8::new(u8::MAX);
+
+/// The instruction sets YUV to RGB can use, slowest first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Simd {
+	/// The plain fixed point version
+	None = 0,
+	#[cfg(target_arch = "x86_64")]
+	Sse2 = 1,
+	#[cfg(target_arch = "x86_64")]
+	Avx2 = 2,
+}
+
+impl Simd {
+	/// Every level this machine can do, slowest first.
+	pub fn available() -> Vec<Simd> {
+		#[allow(unused_mut)]
+		let mut levels = vec![Simd::None];
+
+		#[cfg(target_arch = "x86_64")]
+		{
+			// Every x86_64 has SSE2
+			levels.push(Simd::Sse2);
+			if is_x86_feature_detected!("avx2") {
+				levels.push(Simd::Avx2);
+			}
+		}
+
+		levels
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Simd::None => "none",
+			#[cfg(target_arch = "x86_64")]
+			Simd::Sse2 => "SSE2",
+			#[cfg(target_arch = "x86_64")]
+			Simd::Avx2 => "AVX2",
+		}
+	}
+
+	/// The best we have that isn't more than [set_simd()] said.
+	fn current() -> Self {
+		let most = SIMD.load(Ordering::Relaxed);
+		Self::available()
+			.into_iter()
+			.rev()
+			.find(|level| *level as u8 <= most)
+			.unwrap_or(Simd::None)
+	}
+}
+
+/// One row of YUV. However it's laid out, there's a `U` and a `V` for every
+/// pair of pixels.
+#[derive(Copy, Clone)]
+enum Row<'a> {
+	/// YUYV if chroma_first is false, UYVY if it's true
+	Packed { src: &'a [u8], chroma_first: bool },
+	/// NV12
+	SemiPlanar { y: &'a [u8], uv: &'a [u8] },
+	/// I420
+	Planar {
+		y: &'a [u8],
+		u: &'a [u8],
+		v: &'a [u8],
+	},
+}
+
+impl<'a> Row<'a> {
+	/// Luma for pixel `x` and chroma for the pair it's in.
+	#[inline]
+	fn pair(&self, x: usize) -> (u8, u8, u8, u8) {
+		let pair = x / 2;
+
+		match *self {
+			Row::Packed { src, chroma_first } => {
+				let g = &src[pair * 4..pair * 4 + 4];
+				if chroma_first {
+					(g[1], g[3], g[0], g[2])
+				} else {
+					(g[0], g[2], g[1], g[3])
+				}
+			}
+			Row::SemiPlanar { y, uv } => (
+				y[x],
+				// Odd widths don't have a second pixel in the last pair
+				*y.get(x + 1).unwrap_or(&0),
+				uv[pair * 2],
+				uv[pair * 2 + 1],
+			),
+			Row::Planar { y, u, v } => (y[x], *y.get(x + 1).unwrap_or(&0), u[pair], v[pair]),
+		}
+	}
+}
+
+/// The YUV to RGB matrix in fixed point, scaled by `1 << FIXED_SHIFT`. They
+/// fit in an i16 so the SIMD versions can use multiply-add on 16 bit lanes.
+#[derive(Copy, Clone, Debug)]
+struct Fixed {
+	/// Subtracted from luma before scaling
+	yoff: i16,
+	y: i16,
+	/// V into red
+	rv: i16,
+	/// U and V out of green
+	gu: i16,
+	gv: i16,
+	/// U into blue
+	bu: i16,
+}
+
+const FIXED_SHIFT: u32 = 13;
+const FIXED_ROUND: i32 = 1 << (FIXED_SHIFT - 1);
+
+impl Fixed {
+	/// This is [yuv_to_rgb()] with the green worked out in terms of U and V
+	/// instead of R and B.
+	fn new(space: ColorSpace) -> Self {
+		let (kr, kb) = space.matrix.coefficients();
+		let kg = 1.0 - kr - kb;
+		let (yoff, yscale, cscale) = space.range.scale();
+
+		let fix = |v: f32| (v * (1 << FIXED_SHIFT) as f32).round() as i16;
+		Self {
+			yoff: yoff as i16,
+			y: fix(255.0 / yscale),
+			rv: fix(255.0 * 2.0 * (1.0 - kr) / cscale),
+			gu: fix(255.0 * 2.0 * kb * (1.0 - kb) / (kg * cscale)),
+			gv: fix(255.0 * 2.0 * kr * (1.0 - kr) / (kg * cscale)),
+			bu: fix(255.0 * 2.0 * (1.0 - kb) / cscale),
+		}
+	}
+
+	/// Convert a pair of pixels that share chroma. `rgb` can be 3 bytes long
+	/// for the odd pixel at the end of a row.
+	#[inline]
+	fn pair(&self, y0: u8, y1: u8, u: u8, v: u8, rgb: &mut [u8]) {
+		let (u, v) = (u as i32 - 128, v as i32 - 128);
+
+		// The chroma part is the same for both so we only do it once
+		let r = self.rv as i32 * v;
+		let g = -(self.gu as i32) * u - self.gv as i32 * v;
+		let b = self.bu as i32 * u;
+
+		for (y, px) in [y0, y1].into_iter().zip(rgb.chunks_exact_mut(3)) {
+			let y = (y as i32 - self.yoff as i32) * self.y as i32 + FIXED_ROUND;
+
+			px[0] = ((y + r) >> FIXED_SHIFT).clamp(0, 255) as u8;
+			px[1] = ((y + g) >> FIXED_SHIFT).clamp(0, 255) as u8;
+			px[2] = ((y + b) >> FIXED_SHIFT).clamp(0, 255) as u8;
+		}
+	}
+
+	fn row(&self, simd: Simd, row: Row, rgb: &mut [u8], width: usize) {
+		// The SIMD versions do as much of the row as they can in whole blocks
+		// and tell us how far they got. Always an even number.
+		let done = match simd {
+			Simd::None => 0,
+			// SAFETY: current() only picks what the CPU has
+			#[cfg(target_arch = "x86_64")]
+			Simd::Sse2 => unsafe { x86::row_sse2(self, row, rgb, width) },
+			#[cfg(target_arch = "x86_64")]
+			Simd::Avx2 => unsafe { x86::row_avx2(self, row, rgb, width) },
+		};
+
+		for x in (done..width).step_by(2) {
+			let (y0, y1, u, v) = row.pair(x);
+			let end = (x + 2).min(width);
+			self.pair(y0, y1, u, v, &mut rgb[x * 3..end * 3]);
+		}
+	}
+}
+
+/// Convert every row of an image, across threads if it's big enough.
+/// `row_at` gives the source for a row number.
+fn convert_rows<'a, F>(space: ColorSpace, rgb: &mut [u8], width: usize, height: usize, row_at: F)
+where
+	F: Fn(usize) -> Row<'a> + Sync,
+{
+	if width == 0 || height == 0 {
+		return;
+	}
+
+	let fixed = Fixed::new(space);
+	let simd = Simd::current();
+	let row_len = width * 3;
+	let rgb = &mut rgb[..row_len * height];
+
+	bands::in_bands(rgb, row_len, 1, |first, rgb| {
+		for (idx, out) in rgb.chunks_exact_mut(row_len).enumerate() {
+			fixed.row(simd, row_at(first + idx), out, width);
+		}
+	});
+}
+
+/// Run `f` on the average YUV of every 2x2 block, and the Y of every pixel.
+/// This is how we do 4:2:0 chroma subsampling.
+fn rgb_to_420<Y, C>(
+	space: ColorSpace,
+	rgb: &[u8],
+	width: usize,
+	height: usize,
+	mut luma: Y,
+	mut chroma: C,
+) where
+	Y: FnMut(usize, u8),
+	C: FnMut(usize, u8, u8),
+{
+	let (cw, ch) = chroma_size(width, height);
+
+	for cy in 0..ch {
+		for cx in 0..cw {
+			let mut sum = [0u32; 3];
+			let mut count = 0;
+
+			for y in cy * 2..(cy * 2 + 2).min(height) {
+				for x in cx * 2..(cx * 2 + 2).min(width) {
+					let idx = (y * width + x) * 3;
+					let yuv = rgb_to_yuv(space, rgb[idx], rgb[idx + 1], rgb[idx + 2]);
+					luma(y * width + x, yuv[0]);
+
+					sum[1] += yuv[1] as u32;
+					sum[2] += yuv[2] as u32;
+					count += 1;
+				}
+			}
+
+			let avg = |s: u32| ((s + count / 2) / count) as u8;
+			chroma(cy * cw + cx, avg(sum[1]), avg(sum[2]));
+		}
+	}
+}
+
+/// RGB to planar 4:2:0. `i420` has to be [yuv420_len()] long.
+pub fn rgb_to_i420(space: ColorSpace, rgb: &[u8], i420: &mut [u8], width: usize, height: usize) {
+	let (cw, ch) = chroma_size(width, height);
+	let (luma, chroma) = i420.split_at_mut(width * height);
+	let (uplane, vplane) = chroma.split_at_mut(cw * ch);
+
+	rgb_to_420(
+		space,
+		rgb,
+		width,
+		height,
+		|idx, y| luma[idx] = y,
+		|idx, u, v| {
+			uplane[idx] = u;
+			vplane[idx] = v;
+		},
+	);
+}
+
+/// RGB to NV12. `nv12` has to be [yuv420_len()] long.
+pub fn rgb_to_nv12(space: ColorSpace, rgb: &[u8], nv12: &mut [u8], width: usize, height: usize) {
+	let (luma, chroma) = nv12.split_at_mut(width * height);
+
+	rgb_to_420(
+		space,
+		rgb,
+		width,
+		height,
+		|idx, y| luma[idx] = y,
+		|idx, u, v| {
+			chroma[idx * 2] = u;
+			chroma[idx * 2 + 1] = v;
+		},
+	);
+}
+
+/// RGB to packed 4:2:2, `Y0 U Y1 V`.
+pub fn rgb_to_yuyv(space: ColorSpace, rgb: &[u8], yuyv: &mut [u8], width: usize, height: usize) {
+	rgb_to_packed422(space, rgb, yuyv, width, height, [0, 1, 2, 3]);
+}
+
+/// RGB to packed 4:2:2, `U Y0 V Y1`.
+pub fn rgb_to_uyvy(space: ColorSpace, rgb: &[u8], uyvy: &mut [u8], width: usize, height: usize) {
+	rgb_to_packed422(space, rgb, uyvy, width, height, [1, 0, 3, 2]);
+}
+
+fn rgb_to_packed422(
+	space: ColorSpace,
+	rgb: &[u8],
+	dst: &mut [u8],
+	width: usize,
+	height: usize,
+	order: [usize; 4],
+) {
+	let stride = packed422_stride(width);
+
+	for row in 0..height {
+		let rgb_row = &rgb[row * width * 3..(row + 1) * width * 3];
+		let dst_row = &mut dst[row * stride..(row + 1) * stride];
+
+		for (pair, group) in dst_row.chunks_exact_mut(4).enumerate() {
+			let x = pair * 2;
+			let a = rgb_to_yuv(
+				space,
+				rgb_row[x * 3],
+				rgb_row[x * 3 + 1],
+				rgb_row[x * 3 + 2],
+			);
+			// Odd widths repeat the last pixel
+			let b = if x + 1 < width {
+				rgb_to_yuv(
+					space,
+					rgb_row[x * 3 + 3],
+					rgb_row[x * 3 + 4],
+					rgb_row[x * 3 + 5],
+				)
+			} else {
+				a
+			};
+
+			group[order[0]] = a[0];
+			group[order[1]] = (a[1] as u16 + b[1] as u16).div_ceil(2) as u8;
+			group[order[2]] = b[0];
+			group[order[3]] = (a[2] as u16 + b[2] as u16).div_ceil(2) as u8;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Mutex;
+
+	use super::*;
+
+	const ALL_SPACES: [ColorSpace; 4] = [
+		ColorSpace::BT601_LIMITED,
+		ColorSpace::BT601_FULL,
+		ColorSpace::BT709_LIMITED,
+		ColorSpace::BT709_FULL,
+	];
+
+	/// Black, white, the 75% bars, and the primaries as RGB and the YUV
+	/// they should be. The limited range ones are the usual published tables,
+	/// which are rounded from exactly 75% where we have to use 191, so we
+	/// allow them to be one out.
+	#[rustfmt::skip]
+	const REFERENCE: &[(ColorSpace, [u8; 3], [u8; 3])] = &[
+		(ColorSpace::BT601_LIMITED, [0, 0, 0], [16, 128, 128]),
+		(ColorSpace::BT601_LIMITED, [255, 255, 255], [235, 128, 128]),
+		(ColorSpace::BT601_LIMITED, [191, 191, 191], [180, 128, 128]),
+		(ColorSpace::BT601_LIMITED, [191, 191, 0], [162, 44, 142]),
+		(ColorSpace::BT601_LIMITED, [0, 191, 191], [131, 156, 44]),
+		(ColorSpace::BT601_LIMITED, [0, 191, 0], [112, 72, 58]),
+		(ColorSpace::BT601_LIMITED, [191, 0, 191], [84, 184, 198]),
+		(ColorSpace::BT601_LIMITED, [191, 0, 0], [65, 100, 212]),
+		(ColorSpace::BT601_LIMITED, [0, 0, 191], [35, 212, 114]),
+		(ColorSpace::BT601_LIMITED, [255, 0, 0], [81, 90, 240]),
+		(ColorSpace::BT601_LIMITED, [0, 255, 0], [145, 54, 34]),
+		(ColorSpace::BT601_LIMITED, [0, 0, 255], [41, 240, 110]),
+
+		(ColorSpace::BT601_FULL, [0, 0, 0], [0, 128, 128]),
+		(ColorSpace::BT601_FULL, [255, 255, 255], [255, 128, 128]),
+		(ColorSpace::BT601_FULL, [191, 191, 191], [191, 128, 128]),
+		(ColorSpace::BT601_FULL, [191, 191, 0], [169, 33, 144]),
+		(ColorSpace::BT601_FULL, [0, 191, 191], [134, 160, 33]),
+		(ColorSpace::BT601_FULL, [0, 191, 0], [112, 65, 48]),
+		(ColorSpace::BT601_FULL, [191, 0, 191], [79, 191, 208]),
+		(ColorSpace::BT601_FULL, [191, 0, 0], [57, 96, 224]),
+		(ColorSpace::BT601_FULL, [0, 0, 191], [22, 224, 112]),
+		(ColorSpace::BT601_FULL, [255, 0, 0], [76, 85, 255]),
+		(ColorSpace::BT601_FULL, [0, 255, 0], [150, 44, 21]),
+		(ColorSpace::BT601_FULL, [0, 0, 255], [29, 255, 107]),
+
+		(ColorSpace::BT709_LIMITED, [0, 0, 0], [16, 128, 128]),
+		(ColorSpace::BT709_LIMITED, [255, 255, 255], [235, 128, 128]),
+		(ColorSpace::BT709_LIMITED, [191, 191, 191], [180, 128, 128]),
+		(ColorSpace::BT709_LIMITED, [191, 191, 0], [168, 44, 136]),
+		(ColorSpace::BT709_LIMITED, [0, 191, 191], [145, 147, 44]),
+		(ColorSpace::BT709_LIMITED, [0, 191, 0], [134, 63, 52]),
+		(ColorSpace::BT709_LIMITED, [191, 0, 191], [63, 193, 204]),
+		(ColorSpace::BT709_LIMITED, [191, 0, 0], [51, 109, 212]),
+		(ColorSpace::BT709_LIMITED, [0, 0, 191], [28, 212, 120]),
+		(ColorSpace::BT709_LIMITED, [255, 0, 0], [63, 102, 240]),
+		(ColorSpace::BT709_LIMITED, [0, 255, 0], [173, 42, 26]),
+		(ColorSpace::BT709_LIMITED, [0, 0, 255], [32, 240, 118]),
+
+		(ColorSpace::BT709_FULL, [0, 0, 0], [0, 128, 128]),
+		(ColorSpace::BT709_FULL, [255, 255, 255], [255, 128, 128]),
+		(ColorSpace::BT709_FULL, [191, 191, 191], [191, 128, 128]),
+		(ColorSpace::BT709_FULL, [191, 191, 0], [177, 33, 137]),
+		(ColorSpace::BT709_FULL, [0, 191, 191], [150, 150, 33]),
+		(ColorSpace::BT709_FULL, [0, 191, 0], [137, 54, 41]),
+		(ColorSpace::BT709_FULL, [191, 0, 191], [54, 202, 215]),
+		(ColorSpace::BT709_FULL, [191, 0, 0], [41, 106, 224]),
+		(ColorSpace::BT709_FULL, [0, 0, 191], [14, 224, 119]),
+		(ColorSpace::BT709_FULL, [255, 0, 0], [54, 99, 255]),
+		(ColorSpace::BT709_FULL, [0, 255, 0], [182, 30, 12]),
+		(ColorSpace::BT709_FULL, [0, 0, 255], [18, 255, 116]),
+	];
+
+	/// [set_simd()] is global, so tests that change it take turns
+	static SIMD_LOCK: Mutex<()> = Mutex::new(());
+
+	fn best_simd() -> Simd {
+		*Simd::available().last().unwrap()
+	}
+
+	fn close(a: &[u8], b: &[u8], by: u8) -> bool {
+		a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.abs_diff(*b) <= by)
+	}
+
+	#[test]
+	fn rgb_to_yuv_matches_reference() {
+		for &(space, rgb, yuv) in REFERENCE {
+			let got = rgb_to_yuv(space, rgb[0], rgb[1], rgb[2]);
+			assert!(
+				close(&got, &yuv, 1),
+				"{space:?} {rgb:?} gave {got:?}, not {yuv:?}"
+			);
+		}
+	}
+
+	#[test]
+	fn yuv_to_rgb_matches_reference() {
+		for &(space, rgb, yuv) in REFERENCE {
+			let got = yuv_to_rgb(space, yuv[0], yuv[1], yuv[2]);
+			assert!(
+				close(&got, &rgb, 1),
+				"{space:?} {yuv:?} gave {got:?}, not {rgb:?}"
+			);
+		}
+	}
+
+	/// The fixed point path agrees with the reference too, not just the float
+	/// one it's checked against.
+	#[test]
+	fn fixed_point_matches_reference() {
+		let _simd = SIMD_LOCK.lock().unwrap();
+		set_simd(Simd::None);
+		for &(space, rgb, yuv) in REFERENCE {
+			let yuyv = [yuv[0], yuv[1], yuv[0], yuv[2]];
+			let mut got = [0; 6];
+			yuyv_to_rgb(space, &yuyv, &mut got, 2, 1);
+
+			assert!(
+				close(&got[..3], &rgb, 1),
+				"{space:?} {yuv:?} gave {got:?}, not {rgb:?}"
+			);
+			assert_eq!(got[..3], got[3..]);
+		}
+		set_simd(best_simd());
+	}
+
+	/// Something with colour everywhere, where every 2x2 block is one colour
+	/// so subsampling the chroma doesn't lose anything.
+	fn blocky(width: usize, height: usize) -> Vec<u8> {
+		let mut rgb = vec![0; width * height * 3];
+		for (idx, px) in rgb.chunks_exact_mut(3).enumerate() {
+			let (bx, by) = ((idx % width) / 2, (idx / width) / 2);
+			let seed = (bx * 7919 + by * 104729) as u32;
+			px.copy_from_slice(&[
+				(seed.wrapping_mul(2654435761) >> 24) as u8,
+				(seed.wrapping_mul(2246822519) >> 24) as u8,
+				(seed.wrapping_mul(3266489917) >> 24) as u8,
+			]);
+		}
+		rgb
+	}
+
+	const SIZES: [(usize, usize); 7] = [(1, 1), (2, 2), (3, 3), (5, 4), (4, 5), (17, 9), (64, 33)];
+
+	/// Limited range has fewer steps than RGB, so going there and back can
+	/// be a little out.
+	const ROUND_TRIP_ERROR: u8 = 3;
+
+	type ToYuv = fn(ColorSpace, &[u8], &mut [u8], usize, usize);
+	type FromYuv = fn(ColorSpace, &[u8], &mut [u8], usize, usize);
+	type YuvLen = fn(usize, usize) -> usize;
+
+	fn round_trip(name: &str, len: YuvLen, to: ToYuv, from: FromYuv) {
+		for space in ALL_SPACES {
+			for (width, height) in SIZES {
+				let rgb = blocky(width, height);
+				let mut yuv = vec![0; len(width, height)];
+				let mut back = vec![0; rgb.len()];
+
+				to(space, &rgb, &mut yuv, width, height);
+				from(space, &yuv, &mut back, width, height);
+
+				assert!(
+					close(&rgb, &back, ROUND_TRIP_ERROR),
+					"{name} {space:?} at {width}x{height} didn't come back the same"
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn yuyv_round_trip() {
+		round_trip("YUYV", packed422_len, rgb_to_yuyv, yuyv_to_rgb);
+	}
+
+	#[test]
+	fn uyvy_round_trip() {
+		round_trip("UYVY", packed422_len, rgb_to_uyvy, uyvy_to_rgb);
+	}
+
+	#[test]
+	fn nv12_round_trip() {
+		round_trip("NV12", yuv420_len, rgb_to_nv12, nv12_to_rgb);
+	}
+
+	#[test]
+	fn i420_round_trip() {
+		round_trip("I420", yuv420_len, rgb_to_i420, i420_to_rgb);
+	}
+
+	/// Random bytes that are the same every run
+	fn noise(len: usize, seed: u64) -> Vec<u8> {
+		let mut state = seed | 1;
+		(0..len)
+			.map(|_| {
+				state ^= state << 13;
+				state ^= state >> 7;
+				state ^= state << 17;
+				(state >> 32) as u8
+			})
+			.collect()
+	}
+
+	/// Every SIMD level gives exactly what the plain version does, including
+	/// the odd pixel and whatever's left over past the last whole block.
+	#[test]
+	fn simd_matches_scalar() {
+		let _simd = SIMD_LOCK.lock().unwrap();
+
+		let converters: [(&str, YuvLen, FromYuv); 4] = [
+			("YUYV", packed422_len, yuyv_to_rgb),
+			("UYVY", packed422_len, uyvy_to_rgb),
+			("NV12", yuv420_len, nv12_to_rgb),
+			("I420", yuv420_len, i420_to_rgb),
+		];
+		let sizes = [
+			(1, 1),
+			(3, 5),
+			(15, 3),
+			(17, 7),
+			(31, 9),
+			(33, 5),
+			(65, 3),
+			(127, 11),
+			(641, 67),
+		];
+
+		for (name, len, convert) in converters {
+			for space in ALL_SPACES {
+				for (seed, (width, height)) in sizes.into_iter().enumerate() {
+					let yuv = noise(len(width, height), seed as u64 + 1);
+
+					set_simd(Simd::None);
+					let mut scalar = vec![0; width * height * 3];
+					convert(space, &yuv, &mut scalar, width, height);
+
+					for level in Simd::available() {
+						set_simd(level);
+						let mut rgb = vec![0; width * height * 3];
+						convert(space, &yuv, &mut rgb, width, height);
+
+						assert!(
+							rgb == scalar,
+							"{name} {space:?} at {width}x{height} with {} isn't the same",
+							level.name()
+						);
+					}
+				}
+			}
+		}
+
+		set_simd(best_simd());
+	}
+}
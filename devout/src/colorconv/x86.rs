@@ -0,0 +1,256 @@
+//! SSE2 and AVX2 versions of [super::Fixed::row()].
+//!
+//! Both go the same way. Luma ends up as 16 bit lanes, one per pixel, and
+//! chroma as 16 bit `U V` pairs, one per pair of pixels. Multiply-add on the
+//! pairs does the chroma part of each colour for both pixels at once, which is
+//! then widened to line up with the luma. We pack down to bytes and write the
+//! RGB out by hand because SSE2 can't shuffle bytes.
+
+use std::arch::x86_64::*;
+
+use super::{Fixed, Row, FIXED_ROUND, FIXED_SHIFT};
+
+/// Coefficients splatted across a register, `_128` or `_256` wide.
+struct Consts<T> {
+	yoff: T,
+	half: T,
+	round: T,
+	/// `[y, 0]` so multiply-add with `[luma, 0]` is just the multiply
+	y: T,
+	/// `[0, rv]`, `[-gu, -gv]`, and `[bu, 0]` for multiply-add with `[U, V]`
+	r: T,
+	g: T,
+	b: T,
+}
+
+/// Pairs two i16s into the i32 that multiply-add wants for `[lo, hi]`
+fn pair(lo: i16, hi: i16) -> i32 {
+	(lo as u16 as u32 | (hi as u16 as u32) << 16) as i32
+}
+
+/// Write `n` pixels from separate R, G, and B bytes.
+#[inline(always)]
+fn interleave(r: &[u8], g: &[u8], b: &[u8], rgb: &mut [u8], n: usize) {
+	for (i, px) in rgb[..n * 3].chunks_exact_mut(3).enumerate() {
+		px[0] = r[i];
+		px[1] = g[i];
+		px[2] = b[i];
+	}
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn consts_sse2(f: &Fixed) -> Consts<__m128i> {
+	Consts {
+		yoff: _mm_set1_epi16(f.yoff),
+		half: _mm_set1_epi16(128),
+		round: _mm_set1_epi32(FIXED_ROUND),
+		y: _mm_set1_epi32(pair(f.y, 0)),
+		r: _mm_set1_epi32(pair(0, f.rv)),
+		g: _mm_set1_epi32(pair(-f.gu, -f.gv)),
+		b: _mm_set1_epi32(pair(f.bu, 0)),
+	}
+}
+
+/// 8 pixels. `luma` is 8 i16 and `chroma` is 4 `[U, V]` i16 pairs.
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn pixels_sse2(c: &Consts<__m128i>, luma: __m128i, chroma: __m128i, rgb: &mut [u8]) {
+	let zero = _mm_setzero_si128();
+
+	let luma = _mm_sub_epi16(luma, c.yoff);
+	let ylo = _mm_add_epi32(_mm_madd_epi16(_mm_unpacklo_epi16(luma, zero), c.y), c.round);
+	let yhi = _mm_add_epi32(_mm_madd_epi16(_mm_unpackhi_epi16(luma, zero), c.y), c.round);
+
+	let chroma = _mm_sub_epi16(chroma, c.half);
+
+	let r = channel_sse2(ylo, yhi, chroma, c.r);
+	let g = channel_sse2(ylo, yhi, chroma, c.g);
+	let b = channel_sse2(ylo, yhi, chroma, c.b);
+	interleave(&r, &g, &b, rgb, 8);
+}
+
+/// One of R, G, or B for 8 pixels, in the first 8 bytes.
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn channel_sse2(ylo: __m128i, yhi: __m128i, chroma: __m128i, coeff: __m128i) -> [u8; 16] {
+	// One value per pair, duplicated so there's one per pixel
+	let pairs = _mm_madd_epi16(chroma, coeff);
+	let lo = _mm_add_epi32(ylo, _mm_unpacklo_epi32(pairs, pairs));
+	let hi = _mm_add_epi32(yhi, _mm_unpackhi_epi32(pairs, pairs));
+
+	let lo = _mm_srai_epi32(lo, FIXED_SHIFT as i32);
+	let hi = _mm_srai_epi32(hi, FIXED_SHIFT as i32);
+	let words = _mm_packs_epi32(lo, hi);
+
+	let mut out = [0u8; 16];
+	_mm_storeu_si128(
+		out.as_mut_ptr() as *mut __m128i,
+		_mm_packus_epi16(words, words),
+	);
+	out
+}
+
+/// Returns how many pixels were converted.
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn row_sse2(f: &Fixed, row: Row, rgb: &mut [u8], width: usize) -> usize {
+	let c = consts_sse2(f);
+	let zero = _mm_setzero_si128();
+	let low_bytes = _mm_set1_epi16(0xFF);
+
+	let blocks = width / 8;
+	for block in 0..blocks {
+		let x = block * 8;
+
+		let (luma, chroma) = match row {
+			Row::Packed { src, chroma_first } => {
+				let src = &src[x * 2..x * 2 + 16];
+				let v = _mm_loadu_si128(src.as_ptr() as *const __m128i);
+
+				let even = _mm_and_si128(v, low_bytes);
+				let odd = _mm_srli_epi16(v, 8);
+				if chroma_first {
+					(odd, even)
+				} else {
+					(even, odd)
+				}
+			}
+			Row::SemiPlanar { y, uv } => {
+				let y = &y[x..x + 8];
+				let uv = &uv[x..x + 8];
+				(
+					_mm_unpacklo_epi8(_mm_loadl_epi64(y.as_ptr() as *const __m128i), zero),
+					_mm_unpacklo_epi8(_mm_loadl_epi64(uv.as_ptr() as *const __m128i), zero),
+				)
+			}
+			Row::Planar { y, u, v } => {
+				let y = &y[x..x + 8];
+				let u = &u[x / 2..x / 2 + 4];
+				let v = &v[x / 2..x / 2 + 4];
+
+				let u = _mm_cvtsi32_si128(i32::from_le_bytes([u[0], u[1], u[2], u[3]]));
+				let v = _mm_cvtsi32_si128(i32::from_le_bytes([v[0], v[1], v[2], v[3]]));
+				(
+					_mm_unpacklo_epi8(_mm_loadl_epi64(y.as_ptr() as *const __m128i), zero),
+					_mm_unpacklo_epi8(_mm_unpacklo_epi8(u, v), zero),
+				)
+			}
+		};
+
+		pixels_sse2(&c, luma, chroma, &mut rgb[x * 3..x * 3 + 24]);
+	}
+
+	blocks * 8
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn consts_avx2(f: &Fixed) -> Consts<__m256i> {
+	Consts {
+		yoff: _mm256_set1_epi16(f.yoff),
+		half: _mm256_set1_epi16(128),
+		round: _mm256_set1_epi32(FIXED_ROUND),
+		y: _mm256_set1_epi32(pair(f.y, 0)),
+		r: _mm256_set1_epi32(pair(0, f.rv)),
+		g: _mm256_set1_epi32(pair(-f.gu, -f.gv)),
+		b: _mm256_set1_epi32(pair(f.bu, 0)),
+	}
+}
+
+/// 16 pixels, the same as [pixels_sse2()] but twice as wide. AVX2 unpacks and
+/// packs inside each 128 bit half, so the low half is pixels 0 to 7 the whole
+/// way through and the high half is 8 to 15.
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn pixels_avx2(c: &Consts<__m256i>, luma: __m256i, chroma: __m256i, rgb: &mut [u8]) {
+	let zero = _mm256_setzero_si256();
+
+	let luma = _mm256_sub_epi16(luma, c.yoff);
+	let ylo = _mm256_add_epi32(
+		_mm256_madd_epi16(_mm256_unpacklo_epi16(luma, zero), c.y),
+		c.round,
+	);
+	let yhi = _mm256_add_epi32(
+		_mm256_madd_epi16(_mm256_unpackhi_epi16(luma, zero), c.y),
+		c.round,
+	);
+
+	let chroma = _mm256_sub_epi16(chroma, c.half);
+
+	let r = channel_avx2(ylo, yhi, chroma, c.r);
+	let g = channel_avx2(ylo, yhi, chroma, c.g);
+	let b = channel_avx2(ylo, yhi, chroma, c.b);
+	interleave(&r, &g, &b, rgb, 16);
+}
+
+/// One of R, G, or B for 16 pixels.
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn channel_avx2(ylo: __m256i, yhi: __m256i, chroma: __m256i, coeff: __m256i) -> [u8; 16] {
+	let pairs = _mm256_madd_epi16(chroma, coeff);
+	let lo = _mm256_add_epi32(ylo, _mm256_unpacklo_epi32(pairs, pairs));
+	let hi = _mm256_add_epi32(yhi, _mm256_unpackhi_epi32(pairs, pairs));
+
+	let lo = _mm256_srai_epi32(lo, FIXED_SHIFT as i32);
+	let hi = _mm256_srai_epi32(hi, FIXED_SHIFT as i32);
+	let words = _mm256_packs_epi32(lo, hi);
+	let bytes = _mm256_packus_epi16(words, words);
+	// The pixels are in the first 8 bytes of each half, bring them together
+	let bytes = _mm256_permute4x64_epi64(bytes, 0b00_00_10_00);
+
+	let mut out = [0u8; 16];
+	_mm_storeu_si128(
+		out.as_mut_ptr() as *mut __m128i,
+		_mm256_castsi256_si128(bytes),
+	);
+	out
+}
+
+/// Returns how many pixels were converted.
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn row_avx2(f: &Fixed, row: Row, rgb: &mut [u8], width: usize) -> usize {
+	let c = consts_avx2(f);
+	let low_bytes = _mm256_set1_epi16(0xFF);
+
+	let blocks = width / 16;
+	for block in 0..blocks {
+		let x = block * 16;
+
+		let (luma, chroma) = match row {
+			Row::Packed { src, chroma_first } => {
+				let src = &src[x * 2..x * 2 + 32];
+				let v = _mm256_loadu_si256(src.as_ptr() as *const __m256i);
+
+				let even = _mm256_and_si256(v, low_bytes);
+				let odd = _mm256_srli_epi16(v, 8);
+				if chroma_first {
+					(odd, even)
+				} else {
+					(even, odd)
+				}
+			}
+			Row::SemiPlanar { y, uv } => {
+				let y = &y[x..x + 16];
+				let uv = &uv[x..x + 16];
+				(
+					_mm256_cvtepu8_epi16(_mm_loadu_si128(y.as_ptr() as *const __m128i)),
+					_mm256_cvtepu8_epi16(_mm_loadu_si128(uv.as_ptr() as *const __m128i)),
+				)
+			}
+			Row::Planar { y, u, v } => {
+				let y = &y[x..x + 16];
+				let u = &u[x / 2..x / 2 + 8];
+				let v = &v[x / 2..x / 2 + 8];
+
+				let u = _mm_loadl_epi64(u.as_ptr() as *const __m128i);
+				let v = _mm_loadl_epi64(v.as_ptr() as *const __m128i);
+				(
+					_mm256_cvtepu8_epi16(_mm_loadu_si128(y.as_ptr() as *const __m128i)),
+					_mm256_cvtepu8_epi16(_mm_unpacklo_epi8(u, v)),
+				)
+			}
+		};
+
+		pixels_avx2(&c, luma, chroma, &mut rgb[x * 3..x * 3 + 48]);
+	}
+
+	blocks * 16
+}
@@ -3,6 +3,7 @@ use std::{
 	fs::File,
 	io::{BufWriter, Seek, Write},
 	path::Path,
+	time::{Duration, Instant},
 };
 
 use bytes::BytesMut;
@@ -15,9 +16,24 @@ use openh264::{
 #[rustfmt::skip]
 /*pub*/ use openh264::formats::YUVSource;
 
+#[cfg(feature = "decoder")]
+pub use read::{DecodedFrame, Reader};
+pub use resize::ResizePolicy;
+pub use stats::{FrameStats, FrameType, Stats};
+pub use text::Cue;
+use text::TextTrack;
+pub use threaded::{Backpressure, ThreadedDevout};
 pub use util::Framerate;
 use util::YUV420Wrapper;
 
+pub mod bands;
+pub mod colorconv;
+#[cfg(feature = "decoder")]
+mod read;
+mod resize;
+mod stats;
+mod text;
+mod threaded;
 mod util;
 
 struct WriterWrapper<W: Write + Seek> {
@@ -33,28 +49,26 @@ impl<W: Write + Seek> WriterWrapper<W> {
 		}
 	}
 
-	fn mp4_or_create_with<F>(&mut self, f: F) -> &mut Mp4Writer<W>
+	fn mp4_or_create_with<F>(&mut self, f: F) -> Result<&mut Mp4Writer<W>, DevoutError>
 	where
-		F: FnOnce(W) -> Mp4Writer<W>,
+		F: FnOnce(W) -> Result<Mp4Writer<W>, DevoutError>,
 	{
 		if self.mp4_writer.is_some() {
 			//TODO : gen- not this
-			self.mp4_writer.as_mut().unwrap()
-		} else {
-			// TODO: gen-
-			// WriterWrapper should not exist in a state without the writer or mp4_writer,
-			// but we should maybe not unwrap here, still
-			let writer = self.writer.take().unwrap();
-			let init = || f(writer);
-			self.mp4_writer.get_or_insert_with(init)
+			return Ok(self.mp4_writer.as_mut().unwrap());
 		}
+
+		// If starting the MP4 failed before, the writer went with it
+		let writer = self.writer.take().ok_or(DevoutError::WriterLost)?;
+		Ok(self.mp4_writer.insert(f(writer)?))
 	}
 }
 
 impl<W: Write + Seek> Drop for WriterWrapper<W> {
 	fn drop(&mut self) {
 		if let Some(ref mut writer) = self.mp4_writer {
-			writer.write_end().unwrap()
+			// Nobody to tell if this fails, Devout::done() is for that
+			writer.write_end().ok();
 		}
 	}
 }
@@ -66,12 +80,34 @@ pub struct Devout<W: Write + Seek> {
 	writer: WriterWrapper<W>,
 	sample_buffer: BytesMut,
 	ticks: u64,
+	stats: Stats,
+	on_frame: Option<Box<dyn FnMut(&FrameStats)>>,
+	text: Option<TextTrack>,
+	resize: ResizePolicy<W>,
+	/// How many times we've started a new segment
+	segment: usize,
+	/// Where rescaled frames go when using [ResizePolicy::Rescale]
+	scale_buffer: Vec<u8>,
+	/// The parameter sets in the avcC box of the current MP4
+	param_sets: Option<ParameterSets>,
+	/// How long the next frame lasts, in ticks, if it's not the usual
+	next_duration: Option<u32>,
+}
+
+/// H264 Sequence and Picture Parameter Sets. These are what a decoder needs
+/// to make sense of the slices. The first ones we see go in the MP4's avcC
+/// box.
+struct ParameterSets {
+	sps: Vec<u8>,
+	pps: Vec<u8>,
 }
 
 /// The things we need to encode H264.
 struct Maybeh264 {
 	encoder: Encoder,
 	yuvbuffer: YUVBuffer,
+	width: u32,
+	height: u32,
 }
 
 impl Devout<BufWriter<File>> {
@@ -98,6 +134,14 @@ impl<W: Write + Seek> Devout<W> {
 			writer: WriterWrapper::new(writer),
 			sample_buffer: BytesMut::new(),
 			ticks: 0,
+			stats: Stats::default(),
+			on_frame: None,
+			text: None,
+			resize: ResizePolicy::Rescale,
+			segment: 0,
+			scale_buffer: vec![],
+			param_sets: None,
+			next_duration: None,
 		}
 	}
 
@@ -120,6 +164,14 @@ impl<W: Write + Seek> Devout<W> {
 			writer: WriterWrapper::new(writer),
 			sample_buffer: BytesMut::new(),
 			ticks: 0,
+			stats: Stats::default(),
+			on_frame: None,
+			text: None,
+			resize: ResizePolicy::Rescale,
+			segment: 0,
+			scale_buffer: vec![],
+			param_sets: None,
+			next_duration: None,
 		}
 	}
 
@@ -129,40 +181,207 @@ impl<W: Write + Seek> Devout<W> {
 		self.bitrate_kbps = kbps;
 	}
 
+	/// Choose what happens when the frame size changes partway through. The
+	/// default is [ResizePolicy::Rescale].
+	pub fn set_resize_policy(&mut self, policy: ResizePolicy<W>) {
+		self.resize = policy;
+	}
+
+	/// Add a timed text track next to the video. Only applies if the MP4
+	/// writer has not yet been created, so call it before the first frame.
+	pub fn enable_text_track(&mut self) {
+		self.text.get_or_insert_with(TextTrack::new);
+	}
+
+	/// Make the next frame last `duration` rather than one frame at the
+	/// framerate, for when frames don't come in evenly. It's rounded to the
+	/// video track's timescale and is always at least one tick.
+	pub fn set_next_duration(&mut self, duration: Duration) {
+		let ticks = (duration.as_secs_f64() * self.framerate.timescale() as f64).round();
+		self.next_duration = Some((ticks as u32).max(1));
+	}
+
+	/// Make the next frame an IDR, so it's somewhere a player can seek to.
+	/// The first frame always is, so this does nothing before then.
+	pub fn force_idr(&mut self) {
+		if let Some(encoder) = self.encoder.as_mut() {
+			encoder.encoder.force_intra_frame();
+		}
+	}
+
+	/// Where we are in the video, in the video track's timescale. This is the
+	/// start time of the next frame.
+	pub fn ticks(&self) -> u64 {
+		self.ticks
+	}
+
+	/// Put `text` on the text track from `start` to `end`, both in ticks. Does
+	/// nothing if [Devout::enable_text_track()] wasn't called.
+	///
+	/// Cues are written in order of their start, and any part of a cue that
+	/// overlaps one already written is cut off.
+	pub fn cue<S: Into<String>>(&mut self, start: u64, end: u64, text: S) {
+		if let Some(track) = self.text.as_mut() {
+			track.cue(Cue {
+				start,
+				end,
+				text: text.into(),
+			});
+		}
+	}
+
+	/// Show `text` from the next frame on, until the next call to annotate or
+	/// until the video is done. Does nothing if [Devout::enable_text_track()]
+	/// wasn't called.
+	pub fn annotate<S: Into<String>>(&mut self, text: S) {
+		if let Some(track) = self.text.as_mut() {
+			track.annotate(self.ticks, text.into());
+		}
+	}
+
+	/// Running totals of everything we've written so far.
+	pub fn stats(&self) -> &Stats {
+		&self.stats
+	}
+
+	/// Call `f` after every frame is written with what happened to it.
+	pub fn on_frame<F: FnMut(&FrameStats) + 'static>(&mut self, f: F) {
+		self.on_frame = Some(Box::new(f));
+	}
+
 	fn init_encoder(width: u32, height: u32, kbps: u32) -> Maybeh264 {
 		let encoder =
 			Encoder::with_config(EncoderConfig::new(width, height).set_bitrate_bps(kbps * 1000))
 				.unwrap();
 		let yuvbuffer = YUVBuffer::new(width as usize, height as usize);
 
-		Maybeh264 { encoder, yuvbuffer }
+		Maybeh264 {
+			encoder,
+			yuvbuffer,
+			width,
+			height,
+		}
+	}
+
+	fn encoder(&mut self, width: u32, height: u32) -> &mut Maybeh264 {
+		let kbps = self.bitrate_kbps;
+		self.encoder
+			.get_or_insert_with(|| Self::init_encoder(width, height, kbps))
+	}
+
+	/// Check a frame's size against the encoder's. Returns the size to scale
+	/// the frame to if it has to be rescaled.
+	fn fit(&mut self, width: u32, height: u32) -> Result<Option<(u32, u32)>, DevoutError> {
+		let (ew, eh) = match self.encoder.as_ref() {
+			None => return Ok(None),
+			Some(enc) if enc.width == width && enc.height == height => return Ok(None),
+			Some(enc) => (enc.width, enc.height),
+		};
+
+		match self.resize {
+			ResizePolicy::Rescale => Ok(Some((ew, eh))),
+			ResizePolicy::Error => Err(DevoutError::Resized {
+				expected: (ew, eh),
+				got: (width, height),
+			}),
+			ResizePolicy::NewSegment(_) => {
+				self.new_segment(width, height)?;
+				Ok(None)
+			}
+		}
+	}
+
+	/// Finish the current MP4 and start the next with an encoder for the
+	/// new size.
+	fn new_segment(&mut self, width: u32, height: u32) -> Result<(), DevoutError> {
+		let ResizePolicy::NewSegment(make) = &mut self.resize else {
+			unreachable!()
+		};
+		let writer = make(self.segment + 1)?;
+		self.segment += 1;
+
+		// Whatever was being annotated is still true in the new segment
+		let carried = self
+			.text
+			.as_ref()
+			.and_then(|text| text.open_text().map(str::to_owned));
+
+		self.borrwed_done()?;
+		self.writer = WriterWrapper::new(writer);
+		self.encoder = Some(Self::init_encoder(width, height, self.bitrate_kbps));
+		self.param_sets = None;
+		self.ticks = 0;
+
+		if let Some(text) = self.text.as_mut() {
+			*text = TextTrack::new();
+			if let Some(note) = carried {
+				text.annotate(0, note);
+			}
+		}
+
+		Ok(())
 	}
 
 	/// To be called when you're done writing data. Writes the last of the MP4.
 	///
 	/// This method is called when [Devout] is dropped, but you can call it
 	/// manually here to catch errors.
-	pub fn done(mut self) {
-		self.borrwed_done();
+	pub fn done(mut self) -> Result<(), DevoutError> {
+		self.borrwed_done()
 	}
 
 	/// I want [Devout::done()] to take ownership, but I also want to be able
 	/// to call done directly (and not reimplement) in drop, so they both just
 	/// call this.
-	fn borrwed_done(&mut self) {
-		let mut mp4 = self.writer.mp4_writer.take().unwrap();
-		mp4.write_end().unwrap();
+	fn borrwed_done(&mut self) -> Result<(), DevoutError> {
+		// We might not have a writer if we never got a frame, or if done was
+		// already called and this is the drop.
+		let Some(mut mp4) = self.writer.mp4_writer.take() else {
+			return Ok(());
+		};
+
+		// Even if the subtitles didn't make it, finishing the MP4 means the
+		// video's still watchable
+		let flushed = match self.text.as_mut() {
+			Some(text) => {
+				text.close(self.ticks);
+				text.flush(&mut mp4)
+			}
+			None => Ok(()),
+		};
+		let ended = mp4.write_end();
+
+		flushed?;
+		ended?;
+		Ok(())
 	}
 
 	/// Take a frame, as 24bit RGB, and push it through into the video. If the
 	/// encoder has not yet been initialized, it will be created on first call
 	/// of this function.
-	pub fn frame(&mut self, width: u32, height: u32, data: &[u8]) {
+	///
+	/// If the frame isn't the same size as the first one, what happens is up
+	/// to the [ResizePolicy]. `data` has to be exactly `width * height * 3`
+	/// bytes.
+	pub fn frame(&mut self, width: u32, height: u32, data: &[u8]) -> Result<(), DevoutError> {
 		/* TODO: gen- Write this, lol */
-		#[rustfmt::skip]
-		let encoder = self.encoder.get_or_insert_with(|| Self::init_encoder(width, height, self.bitrate_kbps));
-		encoder.yuvbuffer.read_rgb(data);
-		self.write_frame::<YUV420Wrapper>(width, height, None)
+		let started = Instant::now();
+		check_len(width, height, data, width as usize * height as usize * 3)?;
+
+		match self.fit(width, height)? {
+			None => {
+				self.encoder(width, height).yuvbuffer.read_rgb(data);
+				self.write_frame::<YUV420Wrapper>(width, height, None, started)
+			}
+			Some((ew, eh)) => {
+				let mut scaled = std::mem::take(&mut self.scale_buffer);
+				resize::nearest_rgb(data, width, height, &mut scaled, ew, eh);
+				self.encoder(ew, eh).yuvbuffer.read_rgb(&scaled);
+				self.scale_buffer = scaled;
+
+				self.write_frame::<YUV420Wrapper>(ew, eh, None, started)
+			}
+		}
 	}
 
 	//TODO: gen- terrible name
@@ -173,53 +392,130 @@ impl<W: Write + Seek> Devout<W> {
 	/// stream.
 	///
 	/// YUV data must be planar and arranged so that all Y values appear, then
-	/// all U, then all V.
-	pub fn frame_yuv420(&mut self, width: u32, height: u32, data: &[u8]) {
-		//TODO: check width/height is correct with known width/height AND with data given
-		self.write_frame(
-			width,
-			height,
-			Some(&YUV420Wrapper {
-				width: width as usize,
-				height: height as usize,
-				bytes: data,
-			}),
-		)
+	/// all U, then all V, and be exactly [colorconv::yuv420_len()] bytes.
+	pub fn frame_yuv420(
+		&mut self,
+		width: u32,
+		height: u32,
+		data: &[u8],
+	) -> Result<(), DevoutError> {
+		let started = Instant::now();
+		let expected = colorconv::yuv420_len(width as usize, height as usize);
+		check_len(width, height, data, expected)?;
+
+		match self.fit(width, height)? {
+			None => self.write_frame(
+				width,
+				height,
+				Some(&YUV420Wrapper {
+					width: width as usize,
+					height: height as usize,
+					bytes: data,
+				}),
+				started,
+			),
+			Some((ew, eh)) => {
+				let mut scaled = std::mem::take(&mut self.scale_buffer);
+				resize::nearest_yuv420(data, width, height, &mut scaled, ew, eh);
+
+				let result = self.write_frame(
+					ew,
+					eh,
+					Some(&YUV420Wrapper {
+						width: ew as usize,
+						height: eh as usize,
+						bytes: &scaled,
+					}),
+					started,
+				);
+				self.scale_buffer = scaled;
+
+				result
+			}
+		}
 	}
 
 	/// Take a frame in the YUV colorspce, described by
 	/// [openh264::foramts::YUVSource] and feed it to the encoder.
-	fn frame_yuvsource<Y: YUVSource>(&mut self, source: &Y) {
-		//TODO: check width/height is correct with known width/height AND with data given
-		self.write_frame(source.width() as u32, source.height() as u32, Some(source))
+	fn frame_yuvsource<Y: YUVSource>(&mut self, source: &Y) -> Result<(), DevoutError> {
+		let (width, height) = (source.width() as u32, source.height() as u32);
+
+		// We can't rescale an arbitrary YUVSource
+		if let Some(expected) = self.fit(width, height)? {
+			return Err(DevoutError::Resized {
+				expected,
+				got: (width, height),
+			});
+		}
+
+		self.write_frame(width, height, Some(source), Instant::now())
 	}
 
-	fn write_frame<Y: YUVSource>(&mut self, width: u32, height: u32, yuv: Option<&Y>) {
+	fn write_frame<Y: YUVSource>(
+		&mut self,
+		width: u32,
+		height: u32,
+		yuv: Option<&Y>,
+		started: Instant,
+	) -> Result<(), DevoutError> {
 		#[rustfmt::skip]
 		let encoder = self.encoder.get_or_insert_with(|| Self::init_encoder(width, height, self.bitrate_kbps));
 		let bitstream = match yuv {
-			Some(yuv) => encoder.encoder.encode(yuv).unwrap(),
-			None => encoder.encoder.encode(&encoder.yuvbuffer).unwrap(),
+			Some(yuv) => encoder.encoder.encode(yuv)?,
+			None => encoder.encoder.encode(&encoder.yuvbuffer)?,
 		};
+		let encode_time = started.elapsed();
+
+		// The first frame of a stream is always an IDR and comes with
+		// parameter sets
+		if self.param_sets.is_none() {
+			let ps = Self::parameter_sets(&bitstream).ok_or(DevoutError::NoParameterSets)?;
+			self.param_sets = Some(ps);
+		}
+		let param_sets = self.param_sets.as_ref().unwrap();
 
 		let mp4_init_closure = |writer: W| {
 			Self::init_mp4(
-				&bitstream,
 				writer,
 				&self.framerate,
+				param_sets,
 				width as u16,
 				height as u16,
 			)
 		};
 
-		let mp4_writer = self.writer.mp4_or_create_with(mp4_init_closure);
-		let bytes = Self::fill_sample_buffer(&mut self.sample_buffer, &bitstream);
+		let mp4_writer = self.writer.mp4_or_create_with(mp4_init_closure)?;
+
+		if let Some(text) = self.text.as_mut() {
+			if text.track_id == 0 {
+				mp4_writer.add_track(&TextTrack::track_config(self.framerate.timescale()))?;
+				// Video is always the first track we add
+				text.track_id = 2;
+			}
+		}
+
+		let (bytes, inband_parameter_sets) =
+			Self::fill_sample_buffer(&mut self.sample_buffer, &bitstream, param_sets);
 
 		// IDR frames mark previous frames as unused for reference, which means
 		// this is a good seek point. Without this you get an unseekable MP4
-		let is_sync = bitstream.frame_type() == openh264::encoder::FrameType::IDR;
+		let frame_type = FrameType::from(bitstream.frame_type());
+		let is_sync = frame_type == FrameType::Idr;
+
+		let duration = self
+			.next_duration
+			.take()
+			.unwrap_or_else(|| self.framerate.tpf());
+		let frame_stats = FrameStats {
+			index: self.stats.frames,
+			bytes: bytes.len(),
+			frame_type,
+			encode_time,
+			start_ticks: self.ticks,
+			duration_ticks: duration,
+			inband_parameter_sets,
+		};
 
-		let duration = self.framerate.tpf();
 		let sample = Mp4Sample {
 			start_time: self.ticks,
 			duration,
@@ -229,16 +525,24 @@ impl<W: Write + Seek> Devout<W> {
 		};
 		self.ticks += duration as u64;
 
-		mp4_writer.write_sample(1, &sample).unwrap();
+		mp4_writer.write_sample(1, &sample)?;
+
+		if let Some(text) = self.text.as_mut() {
+			text.flush(mp4_writer)?;
+		}
+
+		// Counted first so the callback sees this frame in stats()
+		self.stats
+			.push(frame_stats.clone(), self.framerate.timescale());
+		if let Some(f) = self.on_frame.as_mut() {
+			f(&frame_stats);
+		}
+
+		Ok(())
 	}
 
-	fn init_mp4(
-		bitstream: &EncodedBitStream,
-		writer: W,
-		framerate: &Framerate,
-		width: u16,
-		height: u16,
-	) -> Mp4Writer<W> {
+	/// Find the first SPS and PPS in the bitstream.
+	fn parameter_sets(bitstream: &EncodedBitStream) -> Option<ParameterSets> {
 		let mut sps = None;
 		let mut pps = None;
 
@@ -264,6 +568,19 @@ impl<W: Write + Seek> Devout<W> {
 			}
 		}
 
+		Some(ParameterSets {
+			sps: sps?,
+			pps: pps?,
+		})
+	}
+
+	fn init_mp4(
+		writer: W,
+		framerate: &Framerate,
+		param_sets: &ParameterSets,
+		width: u16,
+		height: u16,
+	) -> Result<Mp4Writer<W>, DevoutError> {
 		let config = Mp4Config {
 			major_brand: "isom".parse().unwrap(),
 			minor_version: 512,
@@ -276,7 +593,7 @@ impl<W: Write + Seek> Devout<W> {
 			timescale: 1000,
 		};
 
-		let mut mp4_writer = Mp4Writer::write_start(writer, &config).unwrap();
+		let mut mp4_writer = Mp4Writer::write_start(writer, &config)?;
 
 		let track_config = TrackConfig {
 			track_type: mp4::TrackType::Video,
@@ -285,13 +602,13 @@ impl<W: Write + Seek> Devout<W> {
 			media_conf: MediaConfig::AvcConfig(AvcConfig {
 				width,
 				height,
-				seq_param_set: sps.unwrap(),
-				pic_param_set: pps.unwrap(),
+				seq_param_set: param_sets.sps.clone(),
+				pic_param_set: param_sets.pps.clone(),
 			}),
 		};
 
-		mp4_writer.add_track(&track_config).unwrap();
-		mp4_writer
+		mp4_writer.add_track(&track_config)?;
+		Ok(mp4_writer)
 	}
 
 	/// skip the 001 or 0001 of a nal to get to the data. If the nal doesn't
@@ -306,9 +623,26 @@ impl<W: Write + Seek> Devout<W> {
 		}
 	}
 
+	/// Write the bitstream into `buffer` as length prefixed NALs, which is how
+	/// MP4 wants them, and return the sample. The bool is true if we kept
+	/// parameter sets in the sample.
+	///
+	/// openh264 puts an SPS and PPS in front of every IDR frame, not just the
+	/// first. Most of the time they're byte for byte the ones already in the
+	/// avcC box and we can leave them out. But openh264 can hand out new
+	/// parameter sets, with new ids, when it starts a new IDR, and the slices
+	/// after that refer to *those*. We used to drop every SPS and that left
+	/// the decoder with slices pointing at an SPS it never saw, which is why
+	/// the video would freeze partway through. So we only drop the copies that
+	/// match the avcC box and keep anything new in-band.
 	#[inline]
-	fn fill_sample_buffer<'a>(buffer: &'a mut BytesMut, bitstream: &EncodedBitStream) -> Bytes {
+	fn fill_sample_buffer(
+		buffer: &mut BytesMut,
+		bitstream: &EncodedBitStream,
+		param_sets: &ParameterSets,
+	) -> (Bytes, bool) {
 		buffer.clear();
+		let mut inband = false;
 
 		for layer_idx in 0..bitstream.num_layers() {
 			let layer = bitstream.layer(layer_idx).unwrap();
@@ -318,31 +652,74 @@ impl<W: Write + Seek> Devout<W> {
 				let nal_data = Self::nal_data(nal);
 				let length = nal_data.len() as u32;
 
-				// We don't want/need to write out Sequence Parameter Sets
-				// gen, later- I guess we do because if I uncomment the conditional
-				// then the video freezes some of the way in. I suspect this is
-				// related to keyframes, but I haven't looked at the bitstream in
-				// detail yet.
-				//if nal_data[0] & 0x1F != 7 {
+				match nal_data.first().map(|b| b & 0x1F) {
+					Some(7) if nal_data == param_sets.sps => continue,
+					Some(8) if nal_data == param_sets.pps => continue,
+					Some(7) | Some(8) => inband = true,
+					_ => (),
+				}
+
 				buffer.extend_from_slice(&length.to_be_bytes());
 				buffer.extend_from_slice(nal_data);
-				//}
 			}
 		}
 
-		buffer.split().freeze()
+		(buffer.split().freeze(), inband)
 	}
 }
 
 impl<W: Write + Seek> std::ops::Drop for Devout<W> {
 	fn drop(&mut self) {
-		self.borrwed_done();
+		self.borrwed_done().ok();
+	}
+}
+
+/// Make sure a frame is as long as its size says before we read it. We check
+/// before anything else so a bad frame can't start a new segment or encoder.
+fn check_len(width: u32, height: u32, data: &[u8], expected: usize) -> Result<(), DevoutError> {
+	if data.len() == expected {
+		Ok(())
+	} else {
+		Err(DevoutError::WrongLength {
+			size: (width, height),
+			len: data.len(),
+			expected,
+		})
 	}
 }
 
 #[derive(Debug)]
 pub enum DevoutError {
 	Mp4Error(mp4::Error),
+	IoError(std::io::Error),
+	H264Error(openh264::Error),
+	/// The first frame out of the encoder didn't have an SPS and PPS, so we
+	/// can't write the MP4's avcC box.
+	NoParameterSets,
+	/// The MP4 we're reading doesn't have a video track.
+	NoVideoTrack,
+	/// A sample in the MP4 we're reading isn't made of length prefixed NALs.
+	BadSample,
+	/// The frame isn't the size the encoder was set up for and the
+	/// [ResizePolicy] is [ResizePolicy::Error].
+	Resized {
+		expected: (u32, u32),
+		got: (u32, u32),
+	},
+	/// The frame's data isn't as long as a frame that size should be.
+	WrongLength {
+		size: (u32, u32),
+		len: usize,
+		expected: usize,
+	},
+	/// A [ThreadedDevout] queue was full and the frame was thrown away.
+	FrameDropped,
+	/// The [ThreadedDevout] worker thread has gone away, probably because
+	/// it panicked.
+	WorkerGone,
+	/// Starting the MP4 failed and took the writer with it, so there's
+	/// nowhere left to write to.
+	WriterLost,
 }
 
 impl std::error::Error for DevoutError {}
@@ -353,6 +730,28 @@ impl fmt::Display for DevoutError {
 			Self::Mp4Error(mp4e) => {
 				write!(f, "error writing mp4: {mp4e}")
 			}
+			Self::IoError(ioe) => write!(f, "io error: {ioe}"),
+			Self::H264Error(h264e) => write!(f, "h264 error: {h264e}"),
+			Self::NoParameterSets => write!(f, "encoder did not give us an SPS and PPS"),
+			Self::NoVideoTrack => write!(f, "mp4 has no video track"),
+			Self::BadSample => write!(f, "mp4 sample is not length prefixed NALs"),
+			Self::Resized { expected, got } => write!(
+				f,
+				"frame is {}x{} but the video is {}x{}",
+				got.0, got.1, expected.0, expected.1
+			),
+			Self::WrongLength {
+				size,
+				len,
+				expected,
+			} => write!(
+				f,
+				"frame is {len} bytes but {}x{} should be {expected}",
+				size.0, size.1
+			),
+			Self::FrameDropped => write!(f, "encoder queue was full, frame dropped"),
+			Self::WorkerGone => write!(f, "encoder thread is no longer running"),
+			Self::WriterLost => write!(f, "the mp4 couldn't be started and the writer is gone"),
 		}
 	}
 }
@@ -362,3 +761,183 @@ impl From<mp4::Error> for DevoutError {
 		Self::Mp4Error(mp4e)
 	}
 }
+
+impl From<std::io::Error> for DevoutError {
+	fn from(ioe: std::io::Error) -> Self {
+		Self::IoError(ioe)
+	}
+}
+
+impl From<openh264::Error> for DevoutError {
+	fn from(h264e: openh264::Error) -> Self {
+		Self::H264Error(h264e)
+	}
+}
+
+#[cfg(all(test, feature = "decoder"))]
+mod tests {
+	use std::io::Cursor;
+
+	use super::*;
+
+	pub(crate) const WIDTH: usize = 64;
+	pub(crate) const HEIGHT: usize = 48;
+
+	/// A box moving across a gradient, so every frame is different
+	pub(crate) fn test_frame(n: usize) -> Vec<u8> {
+		let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+		for (idx, px) in rgb.chunks_exact_mut(3).enumerate() {
+			let (x, y) = (idx % WIDTH, idx / WIDTH);
+			let inside = (n..n + 8).contains(&x) && (16..32).contains(&y);
+			px.copy_from_slice(&match inside {
+				true => [255, 255, 255],
+				false => [(x * 4) as u8, (y * 5) as u8, (n * 8) as u8],
+			});
+		}
+		rgb
+	}
+
+	/// Encode `frames` frames to an MP4 in memory, forcing an IDR at each of
+	/// `idrs` on top of the first one.
+	fn encode(
+		frames: usize,
+		idrs: &[usize],
+		setup: impl FnOnce(&mut Devout<Cursor<&mut Vec<u8>>>),
+	) -> Vec<u8> {
+		let mut mp4 = vec![];
+		let mut devout = Devout::new(Cursor::new(&mut mp4), Framerate::Thirty);
+		setup(&mut devout);
+
+		for n in 0..frames {
+			if idrs.contains(&n) {
+				devout.force_idr();
+			}
+			devout
+				.frame(WIDTH as u32, HEIGHT as u32, &test_frame(n))
+				.unwrap();
+		}
+
+		assert_eq!(devout.stats().frames, frames as u64);
+		devout.done().unwrap();
+		mp4
+	}
+
+	/// Decode start to finish, then seek to every sync sample and check we
+	/// get the same frame we did the first time. Returns the sync samples.
+	fn check_plays_and_seeks(mp4: Vec<u8>, frames: usize) -> Vec<u32> {
+		let size = mp4.len() as u64;
+		let mut reader = Reader::new(Cursor::new(mp4), size).unwrap();
+		assert_eq!(reader.sample_count(), frames as u32);
+		assert_eq!(
+			(reader.width(), reader.height()),
+			(WIDTH as u32, HEIGHT as u32)
+		);
+
+		let mut rgb = vec![];
+		let mut decoded = vec![];
+		while let Some(frame) = reader.next_frame(&mut rgb).unwrap() {
+			assert_eq!(
+				frame.sample as usize,
+				decoded.len() + 1,
+				"a sample didn't decode"
+			);
+			decoded.push(rgb.clone());
+		}
+		assert_eq!(decoded.len(), frames);
+
+		let syncs = reader.sync_samples().unwrap();
+		assert_eq!(
+			syncs.first(),
+			Some(&1),
+			"the first frame isn't a sync sample"
+		);
+
+		for &sync in &syncs {
+			assert_eq!(reader.seek(sync).unwrap(), sync);
+
+			let frame = reader
+				.next_frame(&mut rgb)
+				.unwrap()
+				.expect("nothing after seeking");
+			assert_eq!(frame.sample, sync);
+			assert!(frame.is_sync);
+			assert!(
+				rgb == decoded[sync as usize - 1],
+				"sample {sync} is different after seeking"
+			);
+		}
+
+		syncs
+	}
+
+	#[test]
+	fn plays_and_seeks() {
+		let mp4 = encode(30, &[15], |_| ());
+		let syncs = check_plays_and_seeks(mp4, 30);
+		assert!(
+			syncs.contains(&16),
+			"the forced IDR isn't a sync sample: {syncs:?}"
+		);
+	}
+
+	#[test]
+	fn plays_and_seeks_with_several_idrs() {
+		let mp4 = encode(40, &[10, 20, 30], |_| ());
+		let syncs = check_plays_and_seeks(mp4, 40);
+		for sync in [11, 21, 31] {
+			assert!(
+				syncs.contains(&sync),
+				"{sync} isn't a sync sample: {syncs:?}"
+			);
+		}
+	}
+
+	#[test]
+	fn plays_and_seeks_with_a_text_track() {
+		let mp4 = encode(20, &[10], |devout| {
+			devout.enable_text_track();
+			devout.annotate("hello");
+		});
+		let syncs = check_plays_and_seeks(mp4, 20);
+		assert!(
+			syncs.contains(&11),
+			"the forced IDR isn't a sync sample: {syncs:?}"
+		);
+	}
+
+	#[test]
+	fn plays_and_seeks_uneven_durations() {
+		let mp4 = encode(20, &[12], |devout| {
+			devout.set_next_duration(Duration::from_millis(100));
+		});
+		check_plays_and_seeks(mp4, 20);
+	}
+
+	#[test]
+	fn wrong_lengths_are_errors() {
+		let mut mp4 = vec![];
+		let mut devout = Devout::new(Cursor::new(&mut mp4), Framerate::Thirty);
+		let (w, h) = (WIDTH as u32, HEIGHT as u32);
+		let rgb = test_frame(0);
+		let yuv = vec![128; colorconv::yuv420_len(WIDTH, HEIGHT)];
+
+		// Before there's an encoder
+		let short = devout.frame(w, h, &rgb[1..]);
+		assert!(matches!(short, Err(DevoutError::WrongLength { .. })));
+		let long = devout.frame_yuv420(w, h, &[&yuv[..], &[0]].concat());
+		assert!(matches!(long, Err(DevoutError::WrongLength { .. })));
+
+		devout.frame(w, h, &rgb).unwrap();
+		devout.frame_yuv420(w, h, &yuv).unwrap();
+
+		// And when the frame would be rescaled, where it's the source that's
+		// read with the wrong size
+		let short = devout.frame(w / 2, h / 2, &rgb[..rgb.len() / 2]);
+		assert!(matches!(short, Err(DevoutError::WrongLength { .. })));
+		let short = devout.frame_yuv420(w * 2, h * 2, &yuv);
+		assert!(matches!(short, Err(DevoutError::WrongLength { .. })));
+
+		assert_eq!(devout.stats().frames, 2);
+		devout.done().unwrap();
+	}
+}
@@ -0,0 +1,206 @@
+use std::{
+	fs::File,
+	io::{BufReader, Read, Seek},
+	path::Path,
+	time::Duration,
+};
+
+use mp4::{Mp4Reader, TrackType};
+use openh264::decoder::Decoder;
+
+use crate::DevoutError;
+
+/// A decoded frame, see [Reader::next_frame()]
+#[derive(Copy, Clone, Debug)]
+pub struct DecodedFrame {
+	/// The MP4 sample this frame came from. These start at 1.
+	pub sample: u32,
+	/// When the frame is shown, from the start of the video.
+	pub timestamp: Duration,
+	pub is_sync: bool,
+	pub width: usize,
+	pub height: usize,
+}
+
+/// Reads the H264 video track of an MP4, like the ones [crate::Devout]
+/// writes, and decodes it to 24bit RGB.
+pub struct Reader<R: Read + Seek> {
+	mp4: Mp4Reader<R>,
+	track_id: u32,
+	timescale: u32,
+	sample_count: u32,
+	width: u32,
+	height: u32,
+	framerate: f64,
+	sps: Vec<u8>,
+	pps: Vec<u8>,
+
+	decoder: Decoder,
+	/// The next sample we're going to read
+	next_sample: u32,
+	/// Where we build Annex B NALs for the decoder
+	annexb: Vec<u8>,
+}
+
+impl Reader<BufReader<File>> {
+	/// Open an MP4 file.
+	pub fn file<P: AsRef<Path>>(path: P) -> Result<Self, DevoutError> {
+		let file = File::open(path)?;
+		let size = file.metadata()?.len();
+		Self::new(BufReader::new(file), size)
+	}
+}
+
+impl<R: Read + Seek> Reader<R> {
+	/// Read the header of the MP4 and find the first H264 video track.
+	pub fn new(reader: R, size: u64) -> Result<Self, DevoutError> {
+		let mp4 = Mp4Reader::read_header(reader, size)?;
+
+		let mut video = mp4
+			.tracks()
+			.iter()
+			.filter(|(_, track)| matches!(track.track_type(), Ok(TrackType::Video)))
+			.map(|(id, _)| *id)
+			.collect::<Vec<u32>>();
+		video.sort();
+		let track_id = *video.first().ok_or(DevoutError::NoVideoTrack)?;
+
+		let track = &mp4.tracks()[&track_id];
+		let sps = track.sequence_parameter_set()?.to_vec();
+		let pps = track.picture_parameter_set()?.to_vec();
+
+		Ok(Self {
+			track_id,
+			timescale: track.timescale(),
+			sample_count: track.sample_count(),
+			width: track.width() as u32,
+			height: track.height() as u32,
+			framerate: track.frame_rate(),
+			sps,
+			pps,
+			decoder: Decoder::new()?,
+			next_sample: 1,
+			annexb: vec![],
+			mp4,
+		})
+	}
+
+	pub fn width(&self) -> u32 {
+		self.width
+	}
+
+	pub fn height(&self) -> u32 {
+		self.height
+	}
+
+	/// Frames per second, as the MP4 says.
+	pub fn framerate(&self) -> f64 {
+		self.framerate
+	}
+
+	/// How many samples, so frames, the video track has.
+	pub fn sample_count(&self) -> u32 {
+		self.sample_count
+	}
+
+	/// The sample numbers of every sync sample (IDR frame) in the track.
+	/// These are the places we can seek to.
+	pub fn sync_samples(&mut self) -> Result<Vec<u32>, DevoutError> {
+		let mut syncs = vec![];
+
+		for sample_id in 1..=self.sample_count {
+			if let Some(sample) = self.mp4.read_sample(self.track_id, sample_id)? {
+				if sample.is_sync {
+					syncs.push(sample_id);
+				}
+			}
+		}
+
+		Ok(syncs)
+	}
+
+	/// Start reading again from the sync sample at or before `sample`. Returns
+	/// the sample we'll actually start from.
+	pub fn seek(&mut self, sample: u32) -> Result<u32, DevoutError> {
+		let mut start = sample.clamp(1, self.sample_count.max(1));
+
+		while start > 1 {
+			match self.mp4.read_sample(self.track_id, start)? {
+				Some(s) if s.is_sync => break,
+				_ => start -= 1,
+			}
+		}
+
+		// A fresh decoder so nothing from before the seek leaks through
+		self.decoder = Decoder::new()?;
+		self.next_sample = start;
+		Ok(start)
+	}
+
+	/// Decode the next frame into `rgb`, which is resized to fit. Returns
+	/// `None` at the end of the video.
+	pub fn next_frame(&mut self, rgb: &mut Vec<u8>) -> Result<Option<DecodedFrame>, DevoutError> {
+		while self.next_sample <= self.sample_count {
+			let sample_id = self.next_sample;
+			self.next_sample += 1;
+
+			let sample = match self.mp4.read_sample(self.track_id, sample_id)? {
+				Some(sample) => sample,
+				None => continue,
+			};
+
+			self.annexb.clear();
+			// The decoder doesn't know about the avcC box, so it gets the
+			// parameter sets in front of every sync sample
+			if sample.is_sync {
+				push_nal(&mut self.annexb, &self.sps);
+				push_nal(&mut self.annexb, &self.pps);
+			}
+			avcc_to_annexb(&sample.bytes, &mut self.annexb)?;
+
+			// The decoder can hold on to a frame, which it shouldn't for the
+			// baseline profile streams we write, but we keep going if it does
+			if let Some(yuv) = self.decoder.decode(&self.annexb)? {
+				let (width, height) = yuv.dimension_rgb();
+				rgb.resize(width * height * 3, 0);
+				yuv.write_rgb8(rgb);
+
+				let ticks = sample.start_time as u128 * 1_000_000_000;
+				let timestamp = Duration::from_nanos((ticks / self.timescale as u128) as u64);
+
+				return Ok(Some(DecodedFrame {
+					sample: sample_id,
+					timestamp,
+					is_sync: sample.is_sync,
+					width,
+					height,
+				}));
+			}
+		}
+
+		Ok(None)
+	}
+}
+
+fn push_nal(buffer: &mut Vec<u8>, nal: &[u8]) {
+	buffer.extend_from_slice(&[0, 0, 0, 1]);
+	buffer.extend_from_slice(nal);
+}
+
+/// MP4 samples are NALs with a 4 byte length in front, the decoder wants
+/// them with start codes.
+fn avcc_to_annexb(mut avcc: &[u8], buffer: &mut Vec<u8>) -> Result<(), DevoutError> {
+	while !avcc.is_empty() {
+		if avcc.len() < 4 {
+			return Err(DevoutError::BadSample);
+		}
+
+		let length = u32::from_be_bytes([avcc[0], avcc[1], avcc[2], avcc[3]]) as usize;
+		let nal = avcc.get(4..4 + length).ok_or(DevoutError::BadSample)?;
+		push_nal(buffer, nal);
+
+		avcc = &avcc[4 + length..];
+	}
+
+	Ok(())
+}
@@ -0,0 +1,101 @@
+use std::{
+	fs::{File, OpenOptions},
+	io::BufWriter,
+	path::{Path, PathBuf},
+};
+
+use crate::colorconv;
+
+/// What [crate::Devout] should do when a frame shows up that's a different
+/// size than the ones before it.
+///
+/// The H264 encoder and the MP4 track are both set up for the first frame's
+/// dimensions, so we can't just keep going.
+pub enum ResizePolicy<W> {
+	/// Nearest neighbour scale the frame to the size of the first frame.
+	Rescale,
+	/// Finish the current MP4 and start a new one at the new size. The
+	/// closure is given the segment number, starting at 1 for the first new
+	/// segment, and returns the writer for it.
+	NewSegment(Box<dyn FnMut(usize) -> std::io::Result<W>>),
+	/// Refuse the frame with [crate::DevoutError::Resized].
+	Error,
+}
+
+impl ResizePolicy<BufWriter<File>> {
+	/// Start new segments next to `path`. If `path` is `clip.mp4` the segments
+	/// are `clip-1.mp4`, `clip-2.mp4`, and so on. If one of those is already
+	/// there the new segment fails rather than overwriting it.
+	pub fn numbered_files<P: AsRef<Path>>(path: P) -> Self {
+		let path = path.as_ref().to_owned();
+
+		ResizePolicy::NewSegment(Box::new(move |segment| {
+			let file = OpenOptions::new()
+				.write(true)
+				.create_new(true)
+				.open(segment_path(&path, segment))?;
+			Ok(BufWriter::new(file))
+		}))
+	}
+}
+
+/// `clip.mp4` -> `clip-{segment}.mp4`
+fn segment_path(path: &Path, segment: usize) -> PathBuf {
+	let stem = path
+		.file_stem()
+		.map(|s| s.to_string_lossy().into_owned())
+		.unwrap_or_default();
+
+	let name = match path.extension() {
+		Some(ext) => format!("{stem}-{segment}.{}", ext.to_string_lossy()),
+		None => format!("{stem}-{segment}"),
+	};
+
+	path.with_file_name(name)
+}
+
+/// Nearest neighbour scale packed 24bit RGB.
+pub(crate) fn nearest_rgb(src: &[u8], sw: u32, sh: u32, dst: &mut Vec<u8>, dw: u32, dh: u32) {
+	let (sw, sh, dw, dh) = (sw as usize, sh as usize, dw as usize, dh as usize);
+	dst.resize(dw * dh * 3, 0);
+	nearest_plane(src, sw, sh, 3, dst, dw, dh);
+}
+
+/// Nearest neighbour scale planar YUV 4:2:0, see [crate::Devout::frame_yuv420()].
+/// Chroma is the size [colorconv::chroma_size()] says, so odd sizes work.
+pub(crate) fn nearest_yuv420(src: &[u8], sw: u32, sh: u32, dst: &mut Vec<u8>, dw: u32, dh: u32) {
+	let (sw, sh, dw, dh) = (sw as usize, sh as usize, dw as usize, dh as usize);
+	let (scw, sch) = colorconv::chroma_size(sw, sh);
+	let (dcw, dch) = colorconv::chroma_size(dw, dh);
+	dst.resize(colorconv::yuv420_len(dw, dh), 0);
+
+	let (dy, dchroma) = dst.split_at_mut(dw * dh);
+	let (du, dv) = dchroma.split_at_mut(dcw * dch);
+	let (sy, schroma) = src.split_at(sw * sh);
+	let (su, sv) = schroma.split_at(scw * sch);
+
+	nearest_plane(sy, sw, sh, 1, dy, dw, dh);
+	nearest_plane(su, scw, sch, 1, du, dcw, dch);
+	nearest_plane(sv, scw, sch, 1, dv, dcw, dch);
+}
+
+fn nearest_plane(
+	src: &[u8],
+	sw: usize,
+	sh: usize,
+	bpp: usize,
+	dst: &mut [u8],
+	dw: usize,
+	dh: usize,
+) {
+	for y in 0..dh {
+		let sy = (y * sh) / dh;
+		for x in 0..dw {
+			let sx = (x * sw) / dw;
+
+			let s = (sy * sw + sx) * bpp;
+			let d = (y * dw + x) * bpp;
+			dst[d..d + bpp].copy_from_slice(&src[s..s + bpp]);
+		}
+	}
+}
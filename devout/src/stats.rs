@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use openh264::encoder;
+
+/// The kind of frame the H264 encoder gave us.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameType {
+	/// Instantaneous Decoder Refresh. A keyframe and a seek point.
+	Idr,
+	I,
+	P,
+	/// The encoder decided this frame wasn't worth any bits.
+	Skip,
+	/// Anything else openh264 might say, which shouldn't happen.
+	Other,
+}
+
+impl From<encoder::FrameType> for FrameType {
+	fn from(ft: encoder::FrameType) -> Self {
+		match ft {
+			encoder::FrameType::IDR => FrameType::Idr,
+			encoder::FrameType::I => FrameType::I,
+			encoder::FrameType::P => FrameType::P,
+			encoder::FrameType::Skip => FrameType::Skip,
+			_ => FrameType::Other,
+		}
+	}
+}
+
+/// What happened to a single frame.
+#[derive(Clone, Debug)]
+pub struct FrameStats {
+	/// Zero based index of the frame in the video.
+	pub index: u64,
+	/// Size of the sample written to the MP4, in bytes.
+	pub bytes: usize,
+	pub frame_type: FrameType,
+	/// How long the colour conversion and encoding took.
+	pub encode_time: Duration,
+	/// Start of the frame in the video track's timescale.
+	pub start_ticks: u64,
+	/// Duration of the frame in the video track's timescale.
+	pub duration_ticks: u32,
+	/// The encoder gave us parameter sets that differ from the ones in the
+	/// MP4's avcC box, so they were kept in the sample.
+	pub inband_parameter_sets: bool,
+}
+
+/// Running totals for everything written so far.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+	/// Frames written.
+	pub frames: u64,
+	/// How many of those were IDR frames.
+	pub idr_frames: u64,
+	/// Bytes of video samples written. Doesn't count the MP4 boxes.
+	pub bytes: u64,
+	/// Total time spent encoding.
+	pub encode_time: Duration,
+	/// Length of the video written so far.
+	pub duration: Duration,
+	/// The most recent frame.
+	pub last_frame: Option<FrameStats>,
+}
+
+impl Stats {
+	pub(crate) fn push(&mut self, frame: FrameStats, timescale: u32) {
+		self.frames += 1;
+		self.bytes += frame.bytes as u64;
+		self.encode_time += frame.encode_time;
+
+		if frame.frame_type == FrameType::Idr {
+			self.idr_frames += 1;
+		}
+
+		// Added up per frame rather than taken from the frame's start because
+		// the ticks start over in each new segment
+		self.duration += Duration::from_secs_f64(frame.duration_ticks as f64 / timescale as f64);
+		self.last_frame = Some(frame);
+	}
+
+	/// Effective bitrate of the video so far in metric kilobits per second.
+	pub fn bitrate_kbps(&self) -> f64 {
+		let secs = self.duration.as_secs_f64();
+		if secs == 0.0 {
+			0.0
+		} else {
+			(self.bytes * 8) as f64 / secs / 1000.0
+		}
+	}
+
+	/// Average time spent encoding a frame.
+	pub fn average_encode_time(&self) -> Duration {
+		if self.frames == 0 {
+			Duration::ZERO
+		} else {
+			self.encode_time.div_f64(self.frames as f64)
+		}
+	}
+
+	/// How many frames per second we could encode if that's all we were
+	/// doing. Handy to see if you're keeping up.
+	pub fn encode_fps(&self) -> f64 {
+		let secs = self.encode_time.as_secs_f64();
+		if secs == 0.0 {
+			0.0
+		} else {
+			self.frames as f64 / secs
+		}
+	}
+}
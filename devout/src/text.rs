@@ -0,0 +1,118 @@
+use std::io::{Seek, Write};
+
+use mp4::{Bytes, MediaConfig, Mp4Sample, Mp4Writer, TrackConfig, TtxtConfig};
+
+/// A bit of text that shows from `start` until `end`.
+///
+/// Times are in the video track's timescale, the same unit as
+/// [crate::Devout::ticks()], so cues line up with frames.
+#[derive(Clone, Debug)]
+pub struct Cue {
+	pub start: u64,
+	pub end: u64,
+	pub text: String,
+}
+
+/// Keeps track of the timed text (tx3g) track. Cues are held here until the
+/// MP4 writer exists and then written in order, with empty samples filling
+/// in any gaps because tx3g wants the samples to cover the whole track.
+pub(crate) struct TextTrack {
+	pub track_id: u32,
+	pending: Vec<Cue>,
+	/// An [crate::Devout::annotate()] span that hasn't been closed yet.
+	open: Option<(u64, String)>,
+	/// How far we've written samples.
+	written: u64,
+}
+
+impl TextTrack {
+	pub fn new() -> Self {
+		Self {
+			track_id: 0,
+			pending: vec![],
+			open: None,
+			written: 0,
+		}
+	}
+
+	pub fn track_config(timescale: u32) -> TrackConfig {
+		TrackConfig {
+			track_type: mp4::TrackType::Subtitle,
+			timescale,
+			language: String::from("und"),
+			media_conf: MediaConfig::TtxtConfig(TtxtConfig {}),
+		}
+	}
+
+	pub fn cue(&mut self, cue: Cue) {
+		self.pending.push(cue);
+	}
+
+	/// End the current span, if any, at `now` and start a new one.
+	pub fn annotate(&mut self, now: u64, text: String) {
+		self.close(now);
+		self.open = Some((now, text));
+	}
+
+	/// The text of the current [crate::Devout::annotate()] span, if any.
+	pub fn open_text(&self) -> Option<&str> {
+		self.open.as_ref().map(|(_, text)| text.as_str())
+	}
+
+	/// End the current span, if any, at `now`.
+	pub fn close(&mut self, now: u64) {
+		if let Some((start, text)) = self.open.take() {
+			self.pending.push(Cue {
+				start,
+				end: now,
+				text,
+			});
+		}
+	}
+
+	/// Write out all the cues we have.
+	pub fn flush<W: Write + Seek>(&mut self, mp4: &mut Mp4Writer<W>) -> Result<(), mp4::Error> {
+		self.pending.sort_by_key(|cue| cue.start);
+
+		for cue in std::mem::take(&mut self.pending) {
+			// Overlapping cues get cut so the samples stay in order
+			let start = cue.start.max(self.written);
+			if cue.end <= start {
+				continue;
+			}
+
+			if start > self.written {
+				self.write(mp4, self.written, start, "")?;
+			}
+			self.write(mp4, start, cue.end, &cue.text)?;
+		}
+
+		Ok(())
+	}
+
+	fn write<W: Write + Seek>(
+		&mut self,
+		mp4: &mut Mp4Writer<W>,
+		start: u64,
+		end: u64,
+		text: &str,
+	) -> Result<(), mp4::Error> {
+		// A tx3g sample is a 16-bit big endian length and then UTF-8
+		let text = &text.as_bytes()[..text.len().min(u16::MAX as usize)];
+		let mut bytes = Vec::with_capacity(text.len() + 2);
+		bytes.extend_from_slice(&(text.len() as u16).to_be_bytes());
+		bytes.extend_from_slice(text);
+
+		let sample = Mp4Sample {
+			start_time: start,
+			duration: (end - start) as u32,
+			rendering_offset: 0,
+			is_sync: true,
+			bytes: Bytes::from(bytes),
+		};
+		mp4.write_sample(self.track_id, &sample)?;
+
+		self.written = end;
+		Ok(())
+	}
+}
@@ -0,0 +1,411 @@
+use std::{
+	io::{Seek, Write},
+	sync::{
+		mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError},
+		Arc, Mutex,
+	},
+	thread::{self, JoinHandle},
+};
+
+use crate::{Cue, Devout, DevoutError, Stats};
+
+/// What [ThreadedDevout] should do when a frame comes in and the queue is
+/// already full.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+	/// Wait for the encoder to catch up. No frames are lost, but the caller
+	/// can be stalled.
+	Block,
+	/// Throw the frame away and tell the caller about it.
+	Drop,
+}
+
+enum Job {
+	Rgb {
+		width: u32,
+		height: u32,
+		data: Vec<u8>,
+	},
+	Yuv420 {
+		width: u32,
+		height: u32,
+		data: Vec<u8>,
+	},
+	Cue(Cue),
+	Annotate(String),
+}
+
+impl Job {
+	fn into_data(self) -> Option<Vec<u8>> {
+		match self {
+			Job::Rgb { data, .. } | Job::Yuv420 { data, .. } => Some(data),
+			Job::Cue(_) | Job::Annotate(_) => None,
+		}
+	}
+}
+
+/// A [Devout] that lives on it's own thread.
+///
+/// Frames are copied into a bounded queue and [ThreadedDevout::frame()]
+/// returns right away; the colour conversion, encoding, and MP4 writing all
+/// happen on the worker. Buffers are handed back from the worker once a frame
+/// is encoded so we're not allocating every frame.
+pub struct ThreadedDevout {
+	tx: Option<SyncSender<Job>>,
+	recycle: Receiver<Vec<u8>>,
+	spare: Option<Vec<u8>>,
+	handle: Option<JoinHandle<Result<(), DevoutError>>>,
+	backpressure: Backpressure,
+	dropped: u64,
+	stats: Arc<Mutex<Stats>>,
+}
+
+impl ThreadedDevout {
+	/// Spawn the worker thread with room for `queue_len` frames.
+	///
+	/// The [Devout] is created *on* the worker by `make` because the H264
+	/// encoder can't be moved between threads once it exists.
+	pub fn new<W, F>(queue_len: usize, backpressure: Backpressure, make: F) -> Self
+	where
+		W: Write + Seek + 'static,
+		F: FnOnce() -> Devout<W> + Send + 'static,
+	{
+		let (tx, rx) = sync_channel(queue_len.max(1));
+		let (recycle_tx, recycle) = channel();
+		let stats = Arc::new(Mutex::new(Stats::default()));
+
+		let worker_stats = stats.clone();
+		let handle = thread::spawn(move || worker(make(), rx, recycle_tx, worker_stats));
+
+		Self {
+			tx: Some(tx),
+			recycle,
+			spare: None,
+			handle: Some(handle),
+			backpressure,
+			dropped: 0,
+			stats,
+		}
+	}
+
+	/// Queue a frame of 24bit RGB. See [Devout::frame()].
+	///
+	/// Returns [DevoutError::FrameDropped] if the queue was full and we're
+	/// using [Backpressure::Drop].
+	pub fn frame(&mut self, width: u32, height: u32, data: &[u8]) -> Result<(), DevoutError> {
+		let data = self.buffer(data);
+		self.send(Job::Rgb {
+			width,
+			height,
+			data,
+		})
+	}
+
+	/// Queue a frame of planar YUV 4:2:0. See [Devout::frame_yuv420()].
+	pub fn frame_yuv420(
+		&mut self,
+		width: u32,
+		height: u32,
+		data: &[u8],
+	) -> Result<(), DevoutError> {
+		let data = self.buffer(data);
+		self.send(Job::Yuv420 {
+			width,
+			height,
+			data,
+		})
+	}
+
+	/// Queue a cue for the text track. See [Devout::cue()].
+	///
+	/// This always waits for room in the queue, even with
+	/// [Backpressure::Drop], because losing text is worse than losing a frame.
+	pub fn cue<S: Into<String>>(
+		&mut self,
+		start: u64,
+		end: u64,
+		text: S,
+	) -> Result<(), DevoutError> {
+		self.send_blocking(Job::Cue(Cue {
+			start,
+			end,
+			text: text.into(),
+		}))
+	}
+
+	/// Show `text` from the next queued frame on. See [Devout::annotate()].
+	pub fn annotate<S: Into<String>>(&mut self, text: S) -> Result<(), DevoutError> {
+		self.send_blocking(Job::Annotate(text.into()))
+	}
+
+	/// How many frames have been thrown away because the queue was full.
+	pub fn dropped(&self) -> u64 {
+		self.dropped
+	}
+
+	/// A copy of the worker's [Stats] as of the last frame it finished.
+	pub fn stats(&self) -> Stats {
+		self.stats.lock().unwrap().clone()
+	}
+
+	/// Encode everything left in the queue and finish the MP4.
+	///
+	/// If any frame failed to encode on the worker, the first of those errors
+	/// is returned here.
+	pub fn done(mut self) -> Result<(), DevoutError> {
+		self.finish()
+	}
+
+	fn buffer(&mut self, data: &[u8]) -> Vec<u8> {
+		let mut buffer = self
+			.spare
+			.take()
+			.or_else(|| self.recycle.try_recv().ok())
+			.unwrap_or_default();
+
+		buffer.clear();
+		buffer.extend_from_slice(data);
+		buffer
+	}
+
+	fn send(&mut self, job: Job) -> Result<(), DevoutError> {
+		let tx = self.tx.as_ref().ok_or(DevoutError::WorkerGone)?;
+
+		match self.backpressure {
+			Backpressure::Block => tx.send(job).map_err(|_| DevoutError::WorkerGone),
+			Backpressure::Drop => match tx.try_send(job) {
+				Ok(()) => Ok(()),
+				Err(TrySendError::Full(job)) => {
+					self.spare = job.into_data();
+					self.dropped += 1;
+					Err(DevoutError::FrameDropped)
+				}
+				Err(TrySendError::Disconnected(_)) => Err(DevoutError::WorkerGone),
+			},
+		}
+	}
+
+	fn send_blocking(&mut self, job: Job) -> Result<(), DevoutError> {
+		let tx = self.tx.as_ref().ok_or(DevoutError::WorkerGone)?;
+		tx.send(job).map_err(|_| DevoutError::WorkerGone)
+	}
+
+	fn finish(&mut self) -> Result<(), DevoutError> {
+		// Dropping the sender is what tells the worker to wrap up
+		self.tx.take();
+
+		match self.handle.take() {
+			Some(handle) => handle.join().map_err(|_| DevoutError::WorkerGone)?,
+			None => Ok(()),
+		}
+	}
+}
+
+impl Drop for ThreadedDevout {
+	fn drop(&mut self) {
+		self.finish().ok();
+	}
+}
+
+fn worker<W: Write + Seek>(
+	mut devout: Devout<W>,
+	rx: Receiver<Job>,
+	recycle: Sender<Vec<u8>>,
+	stats: Arc<Mutex<Stats>>,
+) -> Result<(), DevoutError> {
+	let mut first_error = None;
+
+	while let Ok(job) = rx.recv() {
+		let (result, data) = match job {
+			Job::Cue(cue) => {
+				devout.cue(cue.start, cue.end, cue.text);
+				continue;
+			}
+			Job::Annotate(text) => {
+				devout.annotate(text);
+				continue;
+			}
+			Job::Rgb {
+				width,
+				height,
+				data,
+			} => (devout.frame(width, height, &data), data),
+			Job::Yuv420 {
+				width,
+				height,
+				data,
+			} => (devout.frame_yuv420(width, height, &data), data),
+		};
+
+		if let Err(e) = result {
+			first_error.get_or_insert(e);
+		}
+
+		*stats.lock().unwrap() = devout.stats().clone();
+
+		// The other end might be gone already if we're just draining the
+		// queue, that's fine.
+		recycle.send(data).ok();
+	}
+
+	let done = devout.done();
+
+	match first_error {
+		Some(e) => Err(e),
+		None => done,
+	}
+}
+
+#[cfg(all(test, feature = "decoder"))]
+mod tests {
+	use std::{
+		io::{self, Cursor, SeekFrom},
+		time::Duration,
+	};
+
+	use super::*;
+	use crate::{
+		tests::{test_frame, HEIGHT, WIDTH},
+		Framerate, Reader,
+	};
+
+	/// Somewhere to write the MP4 that we can still get at once the worker's
+	/// done with it.
+	#[derive(Clone, Default)]
+	struct Shared(Arc<Mutex<Cursor<Vec<u8>>>>);
+
+	impl Write for Shared {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.0.lock().unwrap().write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	impl Seek for Shared {
+		fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+			self.0.lock().unwrap().seek(pos)
+		}
+	}
+
+	/// A [ThreadedDevout] whose worker doesn't start until `gate` is sent to
+	/// or dropped, so we know exactly what's in the queue.
+	fn threaded(
+		queue_len: usize,
+		backpressure: Backpressure,
+		gate: Receiver<()>,
+	) -> (ThreadedDevout, Shared) {
+		let out = Shared::default();
+		let writer = out.clone();
+
+		let threaded = ThreadedDevout::new(queue_len, backpressure, move || {
+			gate.recv().ok();
+			Devout::new(writer, Framerate::Thirty)
+		});
+		(threaded, out)
+	}
+
+	/// How many frames come back out of the MP4.
+	fn decoded(out: Shared) -> usize {
+		let mp4 = out.0.lock().unwrap().get_ref().clone();
+		let size = mp4.len() as u64;
+		let mut reader = Reader::new(Cursor::new(mp4), size).unwrap();
+
+		let mut rgb = vec![];
+		let mut frames = 0;
+		while reader.next_frame(&mut rgb).unwrap().is_some() {
+			frames += 1;
+		}
+		frames
+	}
+
+	fn send(threaded: &mut ThreadedDevout, n: usize) -> Result<(), DevoutError> {
+		threaded.frame(WIDTH as u32, HEIGHT as u32, &test_frame(n))
+	}
+
+	#[test]
+	fn drop_counts_what_it_drops() {
+		let (open, gate) = channel();
+		let (mut threaded, out) = threaded(2, Backpressure::Drop, gate);
+
+		// The worker's held up, so two fit and the rest don't
+		let sent: Vec<_> = (0..5).map(|n| send(&mut threaded, n)).collect();
+		assert!(sent[..2].iter().all(Result::is_ok));
+		assert!(sent[2..]
+			.iter()
+			.all(|r| matches!(r, Err(DevoutError::FrameDropped))));
+		let dropped = threaded.dropped();
+		assert_eq!(dropped, 3);
+
+		drop(open);
+		threaded.done().unwrap();
+		assert_eq!(decoded(out) as u64, 5 - dropped);
+	}
+
+	#[test]
+	fn block_never_drops() {
+		let (open, gate) = channel();
+		open.send(()).unwrap();
+		let (mut threaded, out) = threaded(1, Backpressure::Block, gate);
+
+		for n in 0..20 {
+			send(&mut threaded, n).unwrap();
+		}
+		assert_eq!(threaded.dropped(), 0);
+
+		threaded.done().unwrap();
+		assert_eq!(decoded(out), 20);
+	}
+
+	#[test]
+	fn done_finishes_the_queue() {
+		let (open, gate) = channel();
+		let (mut threaded, out) = threaded(8, Backpressure::Block, gate);
+
+		for n in 0..8 {
+			send(&mut threaded, n).unwrap();
+		}
+		// Nothing's been encoded yet when we say we're done
+		assert_eq!(threaded.stats().frames, 0);
+
+		drop(open);
+		threaded.done().unwrap();
+		assert_eq!(decoded(out), 8);
+	}
+
+	#[test]
+	fn buffers_come_back() {
+		let (open, gate) = channel();
+		let (mut threaded, out) = threaded(1, Backpressure::Drop, gate);
+
+		send(&mut threaded, 0).unwrap();
+		// A dropped frame's buffer is the next one we fill
+		assert!(send(&mut threaded, 1).is_err());
+		let spare = threaded.spare.as_ref().unwrap().as_ptr();
+		let data = threaded.buffer(&test_frame(2));
+		let sent = data.as_ptr();
+		assert_eq!(sent, spare);
+
+		// And once the worker's finished with it, it gets sent back
+		drop(open);
+		threaded
+			.send_blocking(Job::Rgb {
+				width: WIDTH as u32,
+				height: HEIGHT as u32,
+				data,
+			})
+			.unwrap();
+
+		let mut back = vec![];
+		for _ in 0..2 {
+			let buffer = threaded.recycle.recv_timeout(Duration::from_secs(10));
+			back.push(buffer.unwrap().as_ptr());
+		}
+		assert!(back.contains(&sent));
+
+		threaded.done().unwrap();
+		assert_eq!(decoded(out), 2);
+	}
+}
@@ -0,0 +1,129 @@
+//! `trichloride bench`, how long the effects and turning camera frames into
+//! RGB take, on one thread and on every core. Everything has to fit in a
+//! 33ms frame for 30fps to keep up.
+
+use std::{
+	hint::black_box,
+	num::NonZeroUsize,
+	thread,
+	time::{Duration, Instant},
+};
+
+use devout::{
+	bands,
+	colorconv::{self, ColorSpace},
+};
+
+use crate::{
+	capture::{BorrowedFrame, Effect, Stage},
+	vex::{ParamValue, Pipeline},
+};
+
+/// How many frames we time each thing over
+const FRAMES: u32 = 30;
+
+pub fn run() {
+	let cores = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+	println!("{cores} cores, SIMD: {}", colorconv::simd_name());
+
+	for (width, height) in [(1280, 720), (1920, 1080)] {
+		// Two frames a little apart so align has something to find and
+		// trichrome isn't just putting back what was there
+		let frames = [test_frame(width, height, 0), test_frame(width, height, 3)];
+		let mut yuyv = vec![0; width * height * 2];
+		colorconv::rgb_to_yuyv(
+			ColorSpace::BT601_LIMITED,
+			&frames[0],
+			&mut yuyv,
+			width,
+			height,
+		);
+
+		let mut colour = Stage::new(Effect::Trichrome);
+		colour.set_param("coloured", ParamValue::Bool(true));
+		colour.set_param("green_delay", ParamValue::Int(4));
+		colour.set_param("blue_delay", ParamValue::Int(8));
+
+		let effects = [
+			("grey trichrome", vec![Stage::new(Effect::Trichrome)]),
+			("colour trichrome", vec![colour.clone()]),
+			("pixelate", vec![Stage::new(Effect::Pixelate)]),
+			("grain", vec![Stage::new(Effect::Grain)]),
+			("align", vec![Stage::new(Effect::Align)]),
+			(
+				"align, colour, grain",
+				vec![Stage::new(Effect::Align), colour, Stage::new(Effect::Grain)],
+			),
+		];
+
+		let time = |threads: usize, f: &mut dyn FnMut(usize)| -> Duration {
+			bands::set_max_threads(threads);
+
+			// Once to get everything allocated and warmed up
+			f(0);
+			let started = Instant::now();
+			for idx in 0..FRAMES as usize {
+				f(black_box(idx));
+			}
+			started.elapsed() / FRAMES
+		};
+
+		let mut results = vec![];
+
+		let mut rgb = vec![0; width * height * 3];
+		let mut convert =
+			|_| colorconv::yuyv_to_rgb(ColorSpace::BT601_LIMITED, &yuyv, &mut rgb, width, height);
+		results.push(("YUYV to RGB", time(1, &mut convert), time(0, &mut convert)));
+
+		for (name, stages) in &effects {
+			let stages: Vec<_> = stages.iter().collect();
+
+			// Fresh each time so both start from the same frames
+			let [single, threaded] = [1, 0].map(|threads| {
+				let mut pipeline = Pipeline::default();
+				pipeline.set(&stages, borrow(&frames[0], width, height));
+
+				time(threads, &mut |idx| {
+					black_box(pipeline.run(borrow(&frames[idx % 2], width, height)));
+				})
+			});
+
+			results.push((*name, single, threaded));
+		}
+
+		println!("\n{width}x{height}, per frame:   1 thread  every core");
+		for (name, single, threaded) in results {
+			println!(
+				"\t{name:<22}{:>7.2}ms {:>7.2}ms  {:>5.1}% of a 30fps frame",
+				single.as_secs_f64() * 1000.0,
+				threaded.as_secs_f64() * 1000.0,
+				threaded.as_secs_f64() * 30.0 * 100.0
+			);
+		}
+	}
+
+	bands::set_max_threads(0);
+}
+
+fn borrow(data: &[u8], width: usize, height: usize) -> BorrowedFrame<'_> {
+	BorrowedFrame {
+		data,
+		width,
+		height,
+	}
+}
+
+/// Something that isn't flat, so nothing gets lucky with the branch
+/// predictor or the clamps, moved `offset` pixels right and down.
+fn test_frame(width: usize, height: usize, offset: usize) -> Vec<u8> {
+	let mut rgb = vec![0; width * height * 3];
+	for (idx, px) in rgb.chunks_exact_mut(3).enumerate() {
+		let (x, y) = (idx % width + offset, idx / width + offset);
+		px.copy_from_slice(&[
+			(x * 255 / width) as u8,
+			(y * 255 / height) as u8,
+			(x ^ y) as u8,
+		]);
+	}
+	rgb
+}
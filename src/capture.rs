@@ -3,25 +3,29 @@ use std::{
 	borrow::BorrowMut,
 	fmt::write,
 	fs::File,
+	io::BufWriter,
 	ops::Deref,
+	path::{Path, PathBuf},
 	sync::{
 		atomic::{AtomicBool, Ordering},
 		mpsc::{channel, Receiver, Sender, TryRecvError},
 		Arc, RwLock, RwLockReadGuard,
 	},
 	thread::{self, JoinHandle},
+	time::{Duration, Instant},
 };
 
-use devout::{Devout, Framerate};
+use devout::{Devout, DevoutError, Framerate, ResizePolicy, Stats};
 use eframe::{egui, epaint::mutex::Mutex};
-use nokhwa::{
-	pixel_format::RgbFormat,
-	utils::{CameraIndex, RequestedFormat, RequestedFormatType},
-	Camera,
-};
+use nokhwa::utils::{CameraControl, CameraFormat, ControlValueSetter, KnownCameraControl};
 
 use crate::{
-	vex::{Tricrideo, Vex},
+	clock::DateTime,
+	controls::{self, CameraSettings},
+	output::NewFile,
+	photo::{Exposures, PhotoRequest},
+	source::{self, FrameSource, Pacing, SourceError, SourceId},
+	vex::{self, Param, ParamValue, Pipeline},
 	Cl3Events,
 };
 
@@ -43,7 +47,7 @@ pub struct Frame {
 }
 
 impl Frame {
-	pub fn borrow(&self) -> BorrowedFrame {
+	pub fn borrow(&self) -> BorrowedFrame<'_> {
 		BorrowedFrame {
 			data: &self.data,
 			width: self.width,
@@ -71,100 +75,486 @@ impl<'a> BorrowedFrame<'a> {
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq)]
 pub enum Effect {
-	Normal,
-	TricrideoGrey,
-	TricrideoColour,
+	Trichrome,
+	Pixelate,
+	Grain,
+	Align,
+}
+
+impl Effect {
+	pub const ALL: [Effect; 4] = [
+		Effect::Trichrome,
+		Effect::Pixelate,
+		Effect::Grain,
+		Effect::Align,
+	];
+
+	/// Lowercase with no spaces, for filenames and the command line
+	pub fn short_name(&self) -> &'static str {
+		match self {
+			Effect::Trichrome => "trichrome",
+			Effect::Pixelate => "pixelate",
+			Effect::Grain => "grain",
+			Effect::Align => "align",
+		}
+	}
+
+	/// What can be changed about it
+	pub fn params(&self) -> &'static [Param] {
+		vex::params(*self)
+	}
 }
 
 impl fmt::Display for Effect {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		match self {
-			Effect::Normal => write!(f, "Normal"),
-			Effect::TricrideoGrey => write!(f, "Grey Trichrome"),
-			Effect::TricrideoColour => write!(f, "Colour Trichrome"),
+			Effect::Trichrome => write!(f, "Trichrome"),
+			Effect::Pixelate => write!(f, "Pixelate"),
+			Effect::Grain => write!(f, "Grain"),
+			Effect::Align => write!(f, "Align"),
+		}
+	}
+}
+
+/// One effect in an [EffectChain]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stage {
+	pub effect: Effect,
+	/// Skip it without taking it out of the chain
+	pub bypass: bool,
+	/// A value for each of [Effect::params]
+	pub params: Vec<ParamValue>,
+}
+
+impl Stage {
+	pub fn new(effect: Effect) -> Self {
+		Self {
+			effect,
+			bypass: false,
+			params: effect.params().iter().map(|p| p.default()).collect(),
+		}
+	}
+
+	/// Change the param called `name`. False if there isn't one.
+	pub fn set_param(&mut self, name: &str, value: ParamValue) -> bool {
+		let params = self.effect.params();
+		match params.iter().position(|p| p.name == name) {
+			Some(idx) => {
+				self.params[idx] = params[idx].clamp(value);
+				true
+			}
+			None => false,
 		}
 	}
 }
 
+/// The effects frames go through, in order. No effects is the camera as it
+/// is, which we call normal.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EffectChain {
+	pub stages: Vec<Stage>,
+}
+
+impl EffectChain {
+	pub fn new(effects: &[Effect]) -> Self {
+		Self {
+			stages: effects.iter().map(|&effect| Stage::new(effect)).collect(),
+		}
+	}
+
+	/// The stages that aren't bypassed
+	pub fn active(&self) -> Vec<&Stage> {
+		self.stages.iter().filter(|s| !s.bypass).collect()
+	}
+
+	/// Lowercase with dashes, for filenames
+	pub fn short_name(&self) -> String {
+		let active = self.active();
+		if active.is_empty() {
+			return String::from("normal");
+		}
+
+		let names: Vec<_> = active.iter().map(|s| s.effect.short_name()).collect();
+		names.join("-")
+	}
+}
+
+impl fmt::Display for EffectChain {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let active = self.active();
+		if active.is_empty() {
+			return write!(f, "Normal");
+		}
+
+		for (idx, stage) in active.iter().enumerate() {
+			if idx > 0 {
+				write!(f, " → ")?;
+			}
+			write!(f, "{}", stage.effect)?;
+
+			// Only the ones that have been changed, so it doesn't go on forever
+			let params = stage.effect.params();
+			let changed: Vec<_> = params
+				.iter()
+				.zip(&stage.params)
+				.filter(|(param, value)| param.default() != **value)
+				.map(|(param, value)| format!("{} {}", param.name, param.display(*value)))
+				.collect();
+			if !changed.is_empty() {
+				write!(f, " ({})", changed.join(", "))?;
+			}
+		}
+		Ok(())
+	}
+}
+
 pub enum CameraEvent {
-	ChangeEffect(Effect),
-	RecordingStarted,
+	ChangeEffects(EffectChain),
+	SetControl(KnownCameraControl, ControlValueSetter),
+	/// Put every camera control back to its default and forget what we saved
+	ResetControls,
+	/// Take the next three frames, a little while apart, for a trichrome photo
+	TakePhoto(PhotoRequest),
+	/// Start sending frames to the encoder, in buffers from this pool
+	RecordingStarted(FramePool),
 	RecordingStopped,
 	Shutdown,
 }
 
+/// How many frames can be waiting for the encoder before we start dropping
+/// them.
+const QUEUE_LEN: usize = 8;
+
+/// Buffers for frames on their way to the encoder. The encoder sends them
+/// back when it's done with them, and there's only ever [QUEUE_LEN] of them,
+/// so if it falls behind we run out and drop frames rather than piling them
+/// up forever.
+pub struct FramePool {
+	recycle: Receiver<Vec<u8>>,
+	allocated: usize,
+}
+
+impl FramePool {
+	/// The pool, and where to send buffers back to.
+	fn new() -> (Self, Sender<Vec<u8>>) {
+		let (tx, rx) = channel();
+
+		let pool = Self {
+			recycle: rx,
+			allocated: 0,
+		};
+		(pool, tx)
+	}
+
+	/// A buffer to put a frame in. If they're all in use we wait for one to
+	/// come back if `wait` is true, or give up if it's not.
+	fn get(&mut self, wait: bool) -> Option<Vec<u8>> {
+		if let Ok(buffer) = self.recycle.try_recv() {
+			return Some(buffer);
+		}
+
+		if self.allocated < QUEUE_LEN {
+			self.allocated += 1;
+			Some(vec![])
+		} else if wait {
+			self.recycle.recv().ok()
+		} else {
+			None
+		}
+	}
+}
+
+/// A frame on its way to the encoder
+struct QueuedFrame {
+	data: Vec<u8>,
+	width: usize,
+	height: usize,
+	/// When it was captured, from the start of the recording
+	timestamp: Duration,
+}
+
+/// Frames that didn't make it into the recording
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Dropped {
+	/// The encoder was too far behind to take them
+	pub encoder: u64,
+	/// The source gave us something we couldn't use
+	pub source: u64,
+}
+
+impl Dropped {
+	pub fn total(&self) -> u64 {
+		self.encoder + self.source
+	}
+}
+
+/// Something like `1280x720 @ 30fps YUYV`
+pub fn format_label(format: &CameraFormat) -> String {
+	format!(
+		"{}x{} @ {}fps {}",
+		format.width(),
+		format.height(),
+		format.frame_rate(),
+		format.format()
+	)
+}
+
+/// The things the camera thread shares with everyone else
+#[derive(Clone)]
+struct Shared {
+	frame: Arc<RwLock<Frame>>,
+	effects: Arc<Mutex<EffectChain>>,
+	/// What the open camera says it can do
+	formats: Arc<Mutex<Vec<CameraFormat>>>,
+	/// What the open camera is actually doing
+	active_format: Arc<Mutex<Option<CameraFormat>>>,
+	/// The open camera's controls, as they were when we last changed one
+	controls: Arc<Mutex<Vec<CameraControl>>>,
+	/// Frames we've lost this recording
+	dropped: Arc<Mutex<Dropped>>,
+}
+
 /// Owner the webcam capture and video encoding threads and everything
 /// to communicate between them and the GUI thread.
 pub struct CameraThread {
 	gui_tx: Sender<Cl3Events>,
+	source: SourceId,
+	/// The format to ask the camera for. None is the highest resolution.
+	format: Option<CameraFormat>,
+	pacing: Pacing,
+	/// Where the current, or last, recording went
+	last_recording: Option<PathBuf>,
 
-	shared_frame: Arc<RwLock<Frame>>,
-	effect: Arc<Mutex<Effect>>,
+	shared: Shared,
+	record_stats: Arc<Mutex<Option<Stats>>>,
 	camera: RespawnableThread<CameraEvent>,
 	encoder: RespawnableThread<MuxerEvent>,
 }
 
 impl CameraThread {
-	pub fn new(sender: Sender<Cl3Events>) -> Self {
+	pub fn new(
+		sender: Sender<Cl3Events>,
+		source: SourceId,
+		format: Option<CameraFormat>,
+		pacing: Pacing,
+	) -> Self {
 		Self {
 			gui_tx: sender,
+			source,
+			format,
+			pacing,
+			last_recording: None,
 
-			shared_frame: Arc::new(RwLock::new(Frame {
-				data: vec![],
-				width: 0,
-				height: 0,
-			})),
-			effect: Arc::new(Mutex::new(Effect::Normal)),
+			shared: Shared {
+				frame: Arc::new(RwLock::new(Frame {
+					data: vec![],
+					width: 0,
+					height: 0,
+				})),
+				effects: Arc::new(Mutex::new(EffectChain::default())),
+				formats: Arc::new(Mutex::new(vec![])),
+				active_format: Arc::new(Mutex::new(None)),
+				controls: Arc::new(Mutex::new(vec![])),
+				dropped: Arc::new(Mutex::new(Dropped::default())),
+			},
+			record_stats: Arc::new(Mutex::new(None)),
 			camera: RespawnableThread::new(),
 			encoder: RespawnableThread::new(),
 		}
 	}
 
-	pub fn camera_tx(&self) -> Sender<CameraEvent> {
-		self.camera.tx.clone()
+	/// Switch to a different set of effects. This goes through the camera
+	/// thread, so it does nothing if that's not running.
+	pub fn set_effects(&self, effects: EffectChain) {
+		self.camera
+			.tx
+			.send(CameraEvent::ChangeEffects(effects))
+			.ok();
 	}
 
 	/// Starts capturing frames from the camera. The [egui::Context] `ctx` is
 	/// used to wakeup the GUI so it can receive the new frame.
 	pub fn start(&mut self, ctx: egui::Context) {
+		self.reap();
 		if self.camera.running() {
 			return;
 		}
 
-		let frame = self.shared_frame.clone();
-		let effect = self.effect.clone();
+		let open = Open {
+			source: self.source.clone(),
+			format: self.format,
+			pacing: self.pacing,
+		};
+		let shared = self.shared.clone();
 		let gui_tx = self.gui_tx.clone();
 		let encoder_tx = self.encoder.tx.clone();
 		self.camera.start(|rx| {
-			thread::spawn(move || camera_runner(ctx, frame, effect, rx, gui_tx, encoder_tx))
+			thread::spawn(move || camera_runner(ctx, open, shared, rx, gui_tx, encoder_tx))
 		});
 	}
 
+	/// Shut the camera thread down and start it again so it picks up a new
+	/// source or format.
+	fn restart(&mut self, ctx: egui::Context) {
+		if self.camera.running() {
+			self.camera.tx.send(CameraEvent::Shutdown).ok();
+			self.join_camera();
+			self.start(ctx);
+		}
+	}
+
+	/// Use a different camera, or the test pattern. If we're capturing, the
+	/// camera thread is restarted with the new source. Does nothing while
+	/// recording.
+	pub fn set_source(&mut self, source: SourceId, ctx: egui::Context) {
+		if source == self.source || self.recording() {
+			return;
+		}
+		self.source = source;
+		// The formats of the old source probably don't mean anything now
+		self.format = None;
+		self.shared.formats.lock().clear();
+
+		self.restart(ctx);
+	}
+
+	pub fn source(&self) -> &SourceId {
+		&self.source
+	}
+
+	/// Ask the camera for a specific format, or None for the highest
+	/// resolution it has. Restarts the camera thread if it's running and
+	/// does nothing while recording.
+	pub fn set_format(&mut self, format: Option<CameraFormat>, ctx: egui::Context) {
+		if format == self.format || self.recording() {
+			return;
+		}
+		self.format = format;
+
+		self.restart(ctx);
+	}
+
+	/// The format we asked for.
+	pub fn requested_format(&self) -> Option<CameraFormat> {
+		self.format
+	}
+
+	/// The formats the source said it supports when it was last opened.
+	pub fn formats(&self) -> Vec<CameraFormat> {
+		self.shared.formats.lock().clone()
+	}
+
+	/// The format the source is actually giving us.
+	pub fn active_format(&self) -> Option<CameraFormat> {
+		*self.shared.active_format.lock()
+	}
+
+	/// The open camera's controls. Empty if it isn't a camera, or isn't open.
+	pub fn controls(&self) -> Vec<CameraControl> {
+		self.shared.controls.lock().clone()
+	}
+
+	/// Change a camera control. It's remembered for next time we open this
+	/// camera.
+	pub fn set_control(&self, control: KnownCameraControl, value: ControlValueSetter) {
+		self.camera
+			.tx
+			.send(CameraEvent::SetControl(control, value))
+			.ok();
+	}
+
+	/// Put all the camera controls back how the camera had them.
+	pub fn reset_controls(&self) {
+		self.camera.tx.send(CameraEvent::ResetControls).ok();
+	}
+
+	/// Take a trichrome photo. The GUI gets a [Cl3Events::PhotoSaved] or a
+	/// [Cl3Events::PhotoError] when it's done.
+	pub fn take_photo(&self, request: PhotoRequest) {
+		if !self.running() {
+			Exposures::new(request).abandon();
+			let msg = String::from("Start the preview to take a photo");
+			self.gui_tx.send(Cl3Events::PhotoError(msg)).ok();
+			return;
+		}
+
+		self.camera.tx.send(CameraEvent::TakePhoto(request)).ok();
+	}
+
 	/// Shuts down, if alive, the camera thread and then the recording thread.
 	pub fn stop(&mut self) {
-		self.camera.tx.send(CameraEvent::Shutdown);
-		self.camera.join();
+		self.camera.tx.send(CameraEvent::Shutdown).ok();
+		self.join_camera();
 		self.stop_recording();
 	}
 
-	pub fn start_recording(&mut self, ctx: egui::Context) {
+	/// Join the threads that have stopped on their own so they can be started
+	/// again, and tell the GUI if one of them crashed. The threads tell the
+	/// GUI themselves about the problems they saw coming.
+	pub fn reap(&mut self) {
+		if self.camera.finished() {
+			self.join_camera();
+		}
+
+		if self.encoder.finished() {
+			// The camera might not know yet, and keep sending it frames
+			self.stop_recording();
+		}
+	}
+
+	fn join_camera(&mut self) {
+		if let Err(e) = self.camera.join() {
+			let msg = format!("The camera thread crashed: {e}");
+			self.gui_tx.send(Cl3Events::CameraError(msg)).ok();
+		}
+	}
+
+	fn join_encoder(&mut self) {
+		if let Err(e) = self.encoder.join() {
+			let msg = format!("The recording thread crashed: {e}");
+			self.gui_tx.send(Cl3Events::RecordingError(msg)).ok();
+		}
+
+		// The camera can get a few more frames in before it hears we've
+		// stopped, and they and the shutdown mustn't end up at the start of
+		// the next recording
+		self.encoder.drain();
+	}
+
+	/// Start recording into `output`, which is likely from
+	/// [OutputSettings::create](crate::output::OutputSettings::create).
+	/// Starts capturing too if we weren't already.
+	pub fn start_recording(&mut self, ctx: egui::Context, output: NewFile) {
+		self.reap();
 		if self.encoder.running() {
 			return;
 		}
 		self.start(ctx);
+		self.last_recording = Some(output.path.clone());
 
-		let frame = self.shared_frame.clone();
-		self.encoder
-			.start(|rx| thread::spawn(|| mp4_h264_writer(frame, rx)));
+		let format = self.shared.active_format.clone();
+		let stats = self.record_stats.clone();
+		*stats.lock() = None;
+		*self.shared.dropped.lock() = Dropped::default();
 
-		self.camera.tx.send(CameraEvent::RecordingStarted);
+		let (pool, recycle) = FramePool::new();
+		let gui_tx = self.gui_tx.clone();
+		self.encoder.start(|rx| {
+			thread::spawn(|| mp4_h264_writer(format, stats, output, recycle, gui_tx, rx))
+		});
+
+		self.camera
+			.tx
+			.send(CameraEvent::RecordingStarted(pool))
+			.ok();
 	}
 
+	/// Stop recording and finish the MP4.
 	pub fn stop_recording(&mut self) {
-		if self.encoder.running() {
-			self.camera.tx.send(CameraEvent::RecordingStopped);
-			self.encoder.tx.send(MuxerEvent::Shutdown).unwrap();
-			self.encoder.join();
+		if self.encoder.started() {
+			self.camera.tx.send(CameraEvent::RecordingStopped).ok();
+			self.encoder.tx.send(MuxerEvent::Shutdown).ok();
+			self.join_encoder();
 		}
 	}
 
@@ -176,129 +566,294 @@ impl CameraThread {
 		self.encoder.running()
 	}
 
-	pub fn frame(&self) -> RwLockReadGuard<Frame> {
-		self.shared_frame.read().unwrap()
+	/// Where the current, or last, recording is going.
+	pub fn last_recording(&self) -> Option<&Path> {
+		self.last_recording.as_deref()
+	}
+
+	/// Encoder statistics for the current, or last, recording.
+	pub fn record_stats(&self) -> Option<Stats> {
+		self.record_stats.lock().clone()
+	}
+
+	/// Frames lost from the current, or last, recording.
+	pub fn dropped(&self) -> Dropped {
+		*self.shared.dropped.lock()
+	}
+
+	pub fn frame(&self) -> RwLockReadGuard<'_, Frame> {
+		self.shared.frame.read().unwrap()
 	}
 }
 
-pub const FRAMERATE: u32 = 30;
+/// What the camera thread should open
+struct Open {
+	source: SourceId,
+	format: Option<CameraFormat>,
+	pacing: Pacing,
+}
 
 fn camera_runner(
 	ctx: egui::Context,
-	frame: Arc<RwLock<Frame>>,
-	effect_type: Arc<Mutex<Effect>>,
+	open: Open,
+	shared: Shared,
 	camera_rx: Receiver<CameraEvent>,
 	gui_tx: Sender<Cl3Events>,
 	encoder_tx: Sender<MuxerEvent>,
 ) -> Receiver<CameraEvent> {
-	let requested_format =
-		RequestedFormat::new::<RgbFormat>(RequestedFormatType::AbsoluteHighestResolution);
-	let mut camera = Camera::new(CameraIndex::Index(0), requested_format).unwrap();
+	let Shared {
+		frame,
+		effects: chain,
+		formats,
+		active_format,
+		controls,
+		dropped,
+	} = shared;
+
+	let source_id = open.source;
+	let mut opened = match source::open(&source_id, open.format, open.pacing) {
+		Ok(source) => source,
+		Err(e) => {
+			let msg = format!("Couldn't open {source_id}: {e}");
+			gui_tx.send(Cl3Events::CameraError(msg)).ok();
+			return camera_rx;
+		}
+	};
+	// Going as fast as we can means going as fast as the encoder can, there's
+	// no reason to lose frames. Cameras won't wait for us though.
+	let wait_for_encoder = open.pacing == Pacing::Fast && !matches!(source_id, SourceId::Camera(_));
+	let source_name = opened.name();
+	// What's been changed on the camera, to put back when it's opened
+	let mut settings = match source_id {
+		SourceId::Camera(_) => Some(CameraSettings::load(&source_name)),
+		_ => None,
+	};
+	let source_format = setup_source(
+		opened.as_mut(),
+		settings.as_ref(),
+		&formats,
+		&active_format,
+		&controls,
+	);
+	// None while the camera's unplugged
+	let mut source = Some(opened);
 
 	println!(
-		"Got camera: {} {}",
-		camera.index(),
-		camera.info().human_name()
+		"Got {source_id}: {source_name} ({})",
+		format_label(&source_format)
 	);
 
-	let width = camera.camera_format().width();
-	let height = camera.camera_format().height();
+	let mut width = source_format.width();
+	let mut height = source_format.height();
 	// working buffer
-	let mut rgb = vec![0; width as usize * height as usize * 3];
-
-	// Can't move Camera between threads, so we set details here
-	{
-		let mut lock = frame.write().unwrap();
-
-		lock.width = width as usize;
-		lock.height = height as usize;
-		lock.data.resize(width as usize * height as usize * 3, 0);
-	}
-
-	println!("Opening stream...");
-	camera.open_stream().unwrap();
-	println!("Opened! Entering loop");
+	let mut rgb = vec![];
+	size_buffers(&frame, &mut rgb, width, height);
 
 	// This is never used outside of this function. Keeping the lock here is safe
-	let mut effect_type = effect_type.lock();
-	let mut effect: Option<Box<dyn Vex>> = None;
-	let mut recording = false;
+	let mut chain = chain.lock();
+	// The effects are kept when we're restarted, so start with them
+	let mut pipeline = Pipeline::default();
+	pipeline.set(&chain.active(), frame.read().unwrap().borrow());
+	// Some while we're recording
+	let mut pool: Option<FramePool> = None;
+	// When the first recorded frame came in, and its timestamp if the source
+	// gave it one
+	let mut record_start: Option<(Instant, Option<Duration>)> = None;
 	let mut shutdown = false;
+	// Files and streams end, and then we only wait around for events
+	let mut finished = false;
+	// When the camera started giving us errors instead of frames
+	let mut failing_since: Option<Instant> = None;
+	// When we last looked for an unplugged camera
+	let mut last_reconnect = Instant::now();
+	// When a control was changed, if we haven't saved it yet
+	let mut settings_changed: Option<Instant> = None;
+	// The photo we're taking, if we are
+	let mut photo: Option<Exposures> = None;
 
 	loop {
 		let mut effect_changed = false;
-		let camera_frame = camera.frame_raw();
+		let mut recording_started = false;
+		let got_frame = if finished {
+			thread::sleep(Duration::from_millis(50));
+			Err(SourceError::Finished)
+		} else if let Some(source) = source.as_mut() {
+			source.frame(&mut rgb)
+		} else {
+			// Unplugged, every so often see if it's back
+			thread::sleep(Duration::from_millis(50));
+
+			if last_reconnect.elapsed() >= RECONNECT_INTERVAL {
+				last_reconnect = Instant::now();
+
+				if let Some(mut reconnected) = reconnect(&source_id, open.format, open.pacing) {
+					let format = setup_source(
+						reconnected.as_mut(),
+						settings.as_ref(),
+						&formats,
+						&active_format,
+						&controls,
+					);
+					println!("{source_name} is back ({})", format_label(&format));
+
+					// It could come back a different size if someone plugged
+					// in something else
+					if (format.width(), format.height()) != (width, height) {
+						width = format.width();
+						height = format.height();
+						size_buffers(&frame, &mut rgb, width, height);
+
+						pipeline.clear();
+						effect_changed = true;
+					}
+
+					source = Some(reconnected);
+					failing_since = None;
+					gui_tx.send(Cl3Events::CameraReconnected).ok();
+				}
+			}
+
+			Err(SourceError::Disconnected)
+		};
+		let captured = Instant::now();
 
 		// Make sure we don't leave events in the receiver
 		loop {
 			match camera_rx.try_recv() {
 				Err(TryRecvError::Empty) => break,
-				Err(TryRecvError::Disconnected) => panic!("camera sender disconnected??"),
+				// Nobody can tell us what to do anymore, so we might as well stop
+				Err(TryRecvError::Disconnected) => {
+					shutdown = true;
+					break;
+				}
 				Ok(CameraEvent::Shutdown) => shutdown = true,
-				Ok(CameraEvent::RecordingStarted) => recording = true,
-				Ok(CameraEvent::RecordingStopped) => recording = false,
-				Ok(CameraEvent::ChangeEffect(effect)) => {
+				Ok(CameraEvent::RecordingStarted(new_pool)) => {
+					pool = Some(new_pool);
+					record_start = None;
+					recording_started = true;
+				}
+				Ok(CameraEvent::RecordingStopped) => pool = None,
+				Ok(CameraEvent::ChangeEffects(effects)) => {
 					effect_changed = true;
-					*effect_type = effect;
+					*chain = effects;
+				}
+				Ok(CameraEvent::SetControl(control, value)) => {
+					if let Some(source) = source.as_mut() {
+						match source.set_control(control, value.clone()) {
+							Ok(()) => {
+								if let Some(settings) = settings.as_mut() {
+									settings.set(control, &value);
+									settings_changed = Some(Instant::now());
+								}
+							}
+							Err(e) => eprintln!("couldn't set {control}: {e}"),
+						}
+
+						// Changing one can change others, like turning auto
+						// exposure off
+						*controls.lock() = source.controls();
+						ctx.request_repaint();
+					}
 				}
+				Ok(CameraEvent::TakePhoto(request)) => match photo {
+					Some(_) => {
+						Exposures::new(request).abandon();
+						let msg = String::from("Already taking a photo");
+						gui_tx.send(Cl3Events::PhotoError(msg)).ok();
+					}
+					None => photo = Some(Exposures::new(request)),
+				},
+				Ok(CameraEvent::ResetControls) => {
+					if let Some(source) = source.as_mut() {
+						controls::reset(source.as_mut());
+						*controls.lock() = source.controls();
+						ctx.request_repaint();
+					}
+
+					if let Some(settings) = settings.as_mut() {
+						settings.clear();
+						settings_changed = Some(Instant::now());
+					}
+				}
+			}
+		}
+
+		// Sliders send a lot of changes, so wait for them to settle down
+		// before writing them out
+		let save_now = settings_changed.is_some_and(|t| shutdown || t.elapsed() >= SAVE_AFTER);
+		if let (true, Some(settings)) = (save_now, &settings) {
+			settings_changed = None;
+			if let Err(e) = settings.save() {
+				eprintln!("couldn't save the camera controls: {e}");
+			}
+		}
+
+		// Mark what we're doing in the recording's text track so you can
+		// tell where the effect changed later
+		if pool.is_some() && (recording_started || effect_changed) {
+			let note = format!("{} · {} · {}", *chain, source_name, DateTime::now());
+			if encoder_tx.send(MuxerEvent::Annotate(note)).is_err() {
+				// It's gone and it'll have said why
+				pool = None;
 			}
 		}
 
 		if effect_changed {
-			match *effect_type {
-				Effect::Normal => effect = None,
-				Effect::TricrideoGrey => {
-					let frame = effect
-						.as_mut()
-						.map(|v| v.frame_out().to_owned())
-						.unwrap_or_else(|| Frame {
-							data: rgb.clone(),
-							width: width as usize,
-							height: height as usize,
-						});
-
-					effect = Some(Box::new(Tricrideo::from_frame(frame)));
-				}
-				Effect::TricrideoColour => {
-					let frame = effect
-						.as_mut()
-						.map(|v| v.frame_out().to_owned())
-						.unwrap_or_else(|| Frame {
-							data: rgb.clone(),
-							width: width as usize,
-							height: height as usize,
-						});
-
-					let mut tri = Tricrideo::from_frame(frame);
-					tri.set_coloured(true);
-					effect = Some(Box::new(tri));
+			// New effects start from what's on screen so it doesn't jump
+			let shown = frame.read().unwrap();
+			pipeline.set(&chain.active(), shown.borrow());
+		}
+
+		match got_frame {
+			Err(SourceError::Finished) => {
+				if !finished {
+					println!("{source_name} has no more frames");
+					finished = true;
+					gui_tx.send(Cl3Events::SourceFinished).ok();
 				}
+				abandon_photo(&mut photo, &gui_tx, "the input ran out");
 			}
-		}
+			Err(SourceError::Disconnected) => (),
+			Err(SourceError::Camera(e)) => {
+				if pool.is_some() {
+					dropped.lock().source += 1;
+				}
 
-		match camera_frame {
-			Err(_e) => (),
-			Ok(cow) => {
-				crate::nv12scary::yuv422_rgb(&cow, &mut rgb, width as usize);
-				//let buffer = cow.decode_image::<RgbFormat>().unwrap();
-				//println!("width = {} | height = {}", buffer.width(), buffer.height());
-				//let buff = buffer.into_raw();
-				//rgb.copy_from_slice(&buff);
+				// A camera that's only given us errors for a while has
+				// probably been unplugged
+				let since = *failing_since.get_or_insert(captured);
+				if since.elapsed() >= LOST_AFTER && matches!(source_id, SourceId::Camera(_)) {
+					eprintln!("{source_name} stopped giving us frames: {e}");
+
+					source = None;
+					pool = None;
+					controls.lock().clear();
+					last_reconnect = Instant::now();
+					gui_tx.send(Cl3Events::CameraDisconnected).ok();
+					abandon_photo(&mut photo, &gui_tx, "the camera was disconnected");
+				} else {
+					// Don't spin on a camera that fails straight away
+					thread::sleep(Duration::from_millis(10));
+				}
+			}
+			Err(e) => {
+				eprintln!("dropping frame: {e}");
+				if pool.is_some() {
+					dropped.lock().source += 1;
+				}
+			}
+			Ok(()) => {
+				failing_since = None;
 
 				{
 					let mut lock = frame.write().unwrap();
 
-					let data = if let Some(effect) = effect.as_mut() {
-						let brwd = BorrowedFrame {
-							data: &rgb,
-							width: width as usize,
-							height: height as usize,
-						};
-
-						effect.effect(brwd).data
-					} else {
-						&rgb
+					let brwd = BorrowedFrame {
+						data: &rgb,
+						width: width as usize,
+						height: height as usize,
 					};
+					let data = pipeline.run(brwd).data;
 
 					unsafe {
 						std::ptr::copy_nonoverlapping(
@@ -307,45 +862,260 @@ fn camera_runner(
 							data.len(),
 						)
 					}
+
+					// The encoder gets its own copy so it doesn't matter if
+					// it's a few frames behind
+					match pool.as_mut().map(|p| p.get(wait_for_encoder)) {
+						None => (),
+						Some(None) => dropped.lock().encoder += 1,
+						Some(Some(mut buffer)) => {
+							buffer.clear();
+							buffer.extend_from_slice(data);
+
+							let pts = source.as_ref().and_then(|s| s.timestamp());
+							let (start, start_pts) = *record_start.get_or_insert((captured, pts));
+							let timestamp = match (pts, start_pts) {
+								(Some(pts), Some(start_pts)) => pts.saturating_sub(start_pts),
+								_ => captured - start,
+							};
+
+							let queued = QueuedFrame {
+								data: buffer,
+								width: width as usize,
+								height: height as usize,
+								timestamp,
+							};
+							if encoder_tx.send(MuxerEvent::Frame(queued)).is_err() {
+								pool = None;
+							}
+						}
+					}
 				}
 
-				if recording {
-					encoder_tx.send(MuxerEvent::FrameReceive).unwrap();
+				// Photos are made from the frames as they come in, the
+				// effects don't come into it
+				if let Some(exposures) = photo.as_mut() {
+					let brwd = BorrowedFrame {
+						data: &rgb,
+						width: width as usize,
+						height: height as usize,
+					};
+
+					if exposures.offer(brwd, captured) {
+						if let Some(exposures) = photo.take() {
+							exposures.develop(gui_tx.clone());
+						}
+					}
 				}
 
 				ctx.request_repaint();
-				gui_tx.send(Cl3Events::FrameReceive).unwrap();
+				gui_tx.send(Cl3Events::FrameReceive).ok();
 			}
 		}
 
 		if shutdown {
+			abandon_photo(&mut photo, &gui_tx, "the camera was stopped");
+			controls.lock().clear();
 			break camera_rx;
 		}
 	}
 }
 
+/// Give up on the photo we're taking, if we are, because we won't get the
+/// rest of its frames.
+fn abandon_photo(photo: &mut Option<Exposures>, gui_tx: &Sender<Cl3Events>, why: &str) {
+	if let Some(exposures) = photo.take() {
+		exposures.abandon();
+		let msg = format!("Couldn't take the photo, {why}");
+		gui_tx.send(Cl3Events::PhotoError(msg)).ok();
+	}
+}
+
+/// How long a camera has to give us nothing but errors before we decide it's
+/// been unplugged
+const LOST_AFTER: Duration = Duration::from_secs(2);
+/// How often to look for it once it's gone
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long after the last change to the camera controls we save them
+const SAVE_AFTER: Duration = Duration::from_secs(1);
+
+/// Put back the camera controls we saved last time and tell everyone what a
+/// newly opened source is doing. Returns its format.
+fn setup_source(
+	source: &mut dyn FrameSource,
+	settings: Option<&CameraSettings>,
+	formats: &Mutex<Vec<CameraFormat>>,
+	active_format: &Mutex<Option<CameraFormat>>,
+	controls: &Mutex<Vec<CameraControl>>,
+) -> CameraFormat {
+	if let Some(settings) = settings {
+		settings.apply(source);
+	}
+
+	let format = source.format();
+	*formats.lock() = source.formats();
+	*active_format.lock() = Some(format);
+	*controls.lock() = source.controls();
+	format
+}
+
+/// Make the working buffer and the shared frame the right size.
+fn size_buffers(frame: &RwLock<Frame>, rgb: &mut Vec<u8>, width: u32, height: u32) {
+	let len = width as usize * height as usize * 3;
+	rgb.resize(len, 0);
+
+	// Can't move Camera between threads, so we set details here
+	let mut lock = frame.write().unwrap();
+	lock.width = width as usize;
+	lock.height = height as usize;
+	lock.data.resize(len, 0);
+}
+
+/// Open an unplugged camera again if it's back.
+fn reconnect(
+	id: &SourceId,
+	format: Option<CameraFormat>,
+	pacing: Pacing,
+) -> Option<Box<dyn FrameSource>> {
+	// Opening a camera that isn't there can be slow and noisy, so look first
+	if let SourceId::Camera(index) = id {
+		if !source::cameras().iter().any(|c| c.index() == index) {
+			return None;
+		}
+	}
+
+	match source::open(id, format, pacing) {
+		Ok(source) => Some(source),
+		Err(e) => {
+			eprintln!("{id} is back but we couldn't open it: {e}");
+			None
+		}
+	}
+}
+
 enum MuxerEvent {
-	FrameReceive,
+	Frame(QueuedFrame),
+	Annotate(String),
 	Shutdown,
 }
 
-fn mp4_h264_writer(frame: Arc<RwLock<Frame>>, rx: Receiver<MuxerEvent>) -> Receiver<MuxerEvent> {
-	let file = File::create("out.mp4").unwrap();
-	let mut h264 = Devout::new(file, Framerate::Whole(FRAMERATE));
+/// Used if we somehow don't know the camera's framerate
+const FALLBACK_FRAMERATE: u32 = 30;
 
-	loop {
-		match rx.recv() {
-			Err(_e) => (),
-			Ok(MuxerEvent::FrameReceive) => {
-				let read = frame.read().unwrap();
-				h264.frame(read.width as u32, read.height as u32, &read.data);
+fn mp4_h264_writer(
+	format: Arc<Mutex<Option<CameraFormat>>>,
+	stats: Arc<Mutex<Option<Stats>>>,
+	output: NewFile,
+	recycle: Sender<Vec<u8>>,
+	gui_tx: Sender<Cl3Events>,
+	rx: Receiver<MuxerEvent>,
+) -> Receiver<MuxerEvent> {
+	let NewFile { path, file } = output;
+	let mut file = Some(BufWriter::new(file));
+	let mut h264 = None;
+
+	// We hang on to each frame until the next one shows up so we know how
+	// long it's meant to be on screen. The note is from an annotation that
+	// came in just before it.
+	let mut held: Option<(QueuedFrame, Option<String>)> = None;
+	let mut note = None;
+	// Once a frame fails we stop trying, whatever it was is probably going
+	// to happen again. We still finish the file with what we've got.
+	let mut failed = false;
+
+	let rx = loop {
+		let (last, last_note, duration) = match rx.recv() {
+			Err(_e) => break rx,
+			Ok(MuxerEvent::Annotate(text)) => {
+				note = Some(text);
+				continue;
+			}
+			Ok(MuxerEvent::Frame(queued)) => {
+				let duration = held
+					.as_ref()
+					.map(|(last, _)| queued.timestamp.saturating_sub(last.timestamp));
+
+				match held.replace((queued, note.take())) {
+					Some((last, last_note)) => (last, last_note, duration),
+					None => continue,
+				}
+			}
+			Ok(MuxerEvent::Shutdown) => match held.take() {
+				// The last frame doesn't have one after it, so it gets the
+				// usual length
+				Some((last, last_note)) if !failed => (last, last_note, None),
+				_ => break rx,
+			},
+		};
+
+		if !failed {
+			// Everything comes from the camera thread after it's opened the
+			// camera, so we know the framerate by now
+			let h264 = h264.get_or_insert_with(|| new_devout(file.take().unwrap(), &path, &format));
+
+			if let Err(e) = write_frame(h264, &last, last_note, duration) {
+				let msg = format!("Couldn't encode a frame, stopping the recording: {e}");
+				gui_tx.send(Cl3Events::RecordingError(msg)).ok();
+				failed = true;
 			}
-			Ok(MuxerEvent::Shutdown) => {
-				h264.done();
-				break rx;
+			*stats.lock() = Some(h264.stats().clone());
+		}
+		recycle.send(last.data).ok();
+
+		if duration.is_none() {
+			// That was the last one
+			break rx;
+		}
+	};
+
+	match h264 {
+		Some(h264) => {
+			if let Err(e) = h264.done() {
+				let msg = format!("Couldn't finish the recording: {e}");
+				gui_tx.send(Cl3Events::RecordingError(msg)).ok();
 			}
 		}
+		None => {
+			// We never got a frame, so don't leave an empty file lying around
+			drop(file);
+			std::fs::remove_file(&path).ok();
+		}
 	}
+	rx
+}
+
+fn write_frame(
+	h264: &mut Devout<BufWriter<File>>,
+	frame: &QueuedFrame,
+	note: Option<String>,
+	duration: Option<Duration>,
+) -> Result<(), DevoutError> {
+	if let Some(note) = note {
+		h264.annotate(note);
+	}
+	if let Some(duration) = duration {
+		h264.set_next_duration(duration);
+	}
+
+	h264.frame(frame.width as u32, frame.height as u32, &frame.data)
+}
+
+fn new_devout(
+	file: BufWriter<File>,
+	path: &Path,
+	format: &Mutex<Option<CameraFormat>>,
+) -> Devout<BufWriter<File>> {
+	let fps = (*format.lock())
+		.map(|f| f.frame_rate())
+		.filter(|fps| *fps > 0)
+		.unwrap_or(FALLBACK_FRAMERATE);
+
+	let mut h264 = Devout::new(file, Framerate::Whole(fps));
+	h264.enable_text_track();
+	// If the camera changes size underneath us, keep going in a new file
+	// rather than making a mess of this one
+	h264.set_resize_policy(ResizePolicy::numbered_files(path));
+	h264
 }
 
 struct RespawnableThread<E> {
@@ -365,17 +1135,57 @@ impl<E> RespawnableThread<E> {
 		}
 	}
 
-	fn join(&mut self) {
-		if let Some(handle) = self.handle.take() {
-			let rx = handle.join().unwrap();
-			self.rx = Some(rx);
+	/// Wait for the thread to finish and take its receiver back. If it
+	/// panicked the receiver went down with it, so we start over with a new
+	/// channel and return what it said.
+	fn join(&mut self) -> Result<(), String> {
+		let Some(handle) = self.handle.take() else {
+			return Ok(());
+		};
+
+		match handle.join() {
+			Ok(rx) => {
+				self.rx = Some(rx);
+				Ok(())
+			}
+			Err(panic) => {
+				let (tx, rx) = channel();
+				self.tx = tx;
+				self.rx = Some(rx);
+
+				Err(panic_message(panic))
+			}
 		}
 	}
 
-	fn running(&self) -> bool {
+	/// Whether the thread was started and hasn't been joined. It might have
+	/// stopped by itself though, see [RespawnableThread::running()].
+	fn started(&self) -> bool {
 		self.handle.is_some()
 	}
 
+	fn running(&self) -> bool {
+		self.handle
+			.as_ref()
+			.map(|h| !h.is_finished())
+			.unwrap_or(false)
+	}
+
+	/// It stopped by itself and needs joining
+	fn finished(&self) -> bool {
+		self.handle
+			.as_ref()
+			.map(|h| h.is_finished())
+			.unwrap_or(false)
+	}
+
+	/// Throw away anything still waiting for a thread that's been joined.
+	fn drain(&mut self) {
+		if let Some(rx) = &self.rx {
+			while rx.try_recv().is_ok() {}
+		}
+	}
+
 	fn start<F>(&mut self, starter: F)
 	where
 		F: FnOnce(Receiver<E>) -> JoinHandle<Receiver<E>>,
@@ -385,3 +1195,14 @@ impl<E> RespawnableThread<E> {
 		self.handle = Some(handle);
 	}
 }
+
+/// What a thread said when it panicked, if it was a string.
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+	match panic.downcast::<String>() {
+		Ok(msg) => *msg,
+		Err(panic) => match panic.downcast::<&str>() {
+			Ok(msg) => msg.to_string(),
+			Err(_) => String::from("it didn't say why"),
+		},
+	}
+}
@@ -0,0 +1,454 @@
+use std::{env, path::PathBuf, time::Duration};
+
+use nokhwa::utils::{CameraFormat, CameraIndex, FrameFormat};
+
+use crate::{
+	capture::{Effect, EffectChain, Stage},
+	photo,
+	source::SourceId,
+	vex::ParamValue,
+};
+
+const USAGE: &str = "usage: trichloride [options]
+       trichloride record [record options] [options]
+       trichloride photo [photo options] [options]
+       trichloride bench
+
+commands:
+    record              record without opening a window. Stops after
+                        --duration, when the input runs out, or on Ctrl-C
+    photo               take one trichrome photo without opening a window
+                        and save it as a PNG
+    bench               time the effects and colour conversion at 720p
+                        and 1080p, on one thread and on every core
+
+record options:
+    --effect <effects>  trichrome, pixelate, grain, or align, or a few of
+                        them one after the other like align,trichrome,grain.
+                        Change their params with a colon, like
+                        pixelate:size=16 or trichrome:coloured=true. grey
+                        and colour are trichrome without and with colour.
+                        Normal if you don't say
+    --duration <time>   how long to record for, like 30s, 2m, or 1h30m
+    --out <path>        record to exactly this file instead of a name from
+                        --name in --output-dir
+
+photo options:
+    --interval <time>   how far apart the three frames are, like 0.5s or
+                        2s. Half a second if you don't say
+    --keep-frames       save the three frames as they came from the camera
+                        too, with -r, -g, and -b on the end
+    --out <path>        save to exactly this file instead of a name from
+                        --name in --output-dir
+
+options:
+    --camera <index>    open the camera at this index, or 'test' for a test
+                        pattern that doesn't need a camera
+    --input <path>      read an MP4, a Y4M, or a directory of PNGs or JPEGs
+                        instead of a camera. '-' reads Y4M from stdin, or
+                        raw frames if you give a --format too
+    --fast              go through inputs as fast as we can instead of at
+                        the speed they're meant to play at
+    --format <format>   capture format as WIDTHxHEIGHT@FPS, optionally with
+                        :FOURCC on the end. Like 1280x720@30:YUYV
+    --output-dir <dir>  where recordings go. The current directory if you
+                        don't say
+    --name <template>   what recordings are called. {date}, {time},
+                        {effect}, and {take} are filled in, and it's
+                        trichloride-{date}-{time}-{effect}-{take}.mp4 if
+                        you don't say. Nothing is ever overwritten
+    --list-cameras      print the cameras we can see and exit
+    --list-formats      print the formats of the camera and exit
+    -h, --help          print this and exit";
+
+/// What we've been asked to do
+#[derive(Debug, Default)]
+pub enum Command {
+	/// Open the window
+	#[default]
+	Gui,
+	Record(RecordArgs),
+	Photo(PhotoArgs),
+	/// Time the effects and print how long they take
+	Bench,
+}
+
+#[derive(Debug, Default)]
+pub struct RecordArgs {
+	pub effects: EffectChain,
+	/// None is until we're stopped or the input ends
+	pub duration: Option<Duration>,
+	/// None to use --output-dir and --name
+	pub out: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct PhotoArgs {
+	pub interval: Duration,
+	pub keep_frames: bool,
+	/// None to use --output-dir and --name
+	pub out: Option<PathBuf>,
+}
+
+impl Default for PhotoArgs {
+	fn default() -> Self {
+		Self {
+			interval: photo::DEFAULT_INTERVAL,
+			keep_frames: false,
+			out: None,
+		}
+	}
+}
+
+/// Command line options. There's not many of them so we parse them by hand.
+#[derive(Debug, Default)]
+pub struct Args {
+	pub command: Command,
+	/// From --camera or --input
+	pub source: Option<SourceId>,
+	pub fast: bool,
+	pub format: Option<FormatArg>,
+	pub output_dir: Option<PathBuf>,
+	pub name: Option<String>,
+	pub list_cameras: bool,
+	pub list_formats: bool,
+}
+
+/// A capture format from the command line. The FourCC is optional, if it's
+/// missing we take whatever the camera has at that size and rate.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FormatArg {
+	pub width: u32,
+	pub height: u32,
+	pub fps: u32,
+	pub fourcc: Option<FrameFormat>,
+}
+
+impl FormatArg {
+	fn parse(s: &str) -> Result<Self, String> {
+		let bad = || format!("format should look like 1280x720@30 or 1280x720@30:YUYV, not '{s}'");
+
+		let (size, rest) = s.split_once('@').ok_or_else(bad)?;
+		let (width, height) = size.split_once('x').ok_or_else(bad)?;
+		let (fps, fourcc) = match rest.split_once(':') {
+			Some((fps, fourcc)) => (fps, Some(fourcc)),
+			None => (rest, None),
+		};
+
+		let fourcc = match fourcc.map(|f| f.to_ascii_uppercase()) {
+			None => None,
+			Some(f) => Some(match f.as_str() {
+				"MJPG" | "MJPEG" => FrameFormat::MJPEG,
+				"YUYV" | "YUY2" => FrameFormat::YUYV,
+				"NV12" => FrameFormat::NV12,
+				"GRAY" | "GREY" | "Y8" => FrameFormat::GRAY,
+				"RGB" | "RGB3" | "RAWRGB" => FrameFormat::RAWRGB,
+				_ => return Err(format!("unknown FourCC '{f}'")),
+			}),
+		};
+
+		Ok(Self {
+			width: width.parse().map_err(|_| bad())?,
+			height: height.parse().map_err(|_| bad())?,
+			fps: fps.parse().map_err(|_| bad())?,
+			fourcc,
+		})
+	}
+
+	/// Whether `format` is what was asked for.
+	pub fn matches(&self, format: &CameraFormat) -> bool {
+		format.width() == self.width
+			&& format.height() == self.height
+			&& format.frame_rate() == self.fps
+			&& self.fourcc.map(|f| f == format.format()).unwrap_or(true)
+	}
+}
+
+impl Args {
+	/// Parse the process arguments. Prints usage and exits if they're bad or
+	/// if help was asked for.
+	pub fn parse() -> Self {
+		match Self::parse_from(env::args().skip(1)) {
+			Ok(Some(args)) => args,
+			Ok(None) => {
+				println!("{USAGE}");
+				std::process::exit(0);
+			}
+			Err(e) => {
+				eprintln!("{e}\n\n{USAGE}");
+				std::process::exit(1);
+			}
+		}
+	}
+
+	/// Returns `Ok(None)` if help was asked for.
+	fn parse_from<I: Iterator<Item = String>>(args: I) -> Result<Option<Self>, String> {
+		let mut args = args.peekable();
+		let mut parsed = Args::default();
+
+		match args.peek().map(String::as_str) {
+			Some("record") => {
+				args.next();
+				parsed.command = Command::Record(RecordArgs::default());
+			}
+			Some("photo") => {
+				args.next();
+				parsed.command = Command::Photo(PhotoArgs::default());
+			}
+			Some("bench") => {
+				args.next();
+				parsed.command = Command::Bench;
+			}
+			_ => (),
+		}
+
+		while let Some(arg) = args.next() {
+			match arg.as_str() {
+				"--effect" | "--duration" => {
+					let Command::Record(record) = &mut parsed.command else {
+						return Err(format!("{arg} only means something with record"));
+					};
+					let value = value(&mut args, &arg)?;
+
+					match arg.as_str() {
+						"--effect" => record.effects = parse_effects(&value)?,
+						_ => record.duration = Some(parse_duration(&value)?),
+					}
+				}
+				"--interval" | "--keep-frames" => {
+					let Command::Photo(photo) = &mut parsed.command else {
+						return Err(format!("{arg} only means something with photo"));
+					};
+
+					match arg.as_str() {
+						"--interval" => photo.interval = parse_duration(&value(&mut args, &arg)?)?,
+						_ => photo.keep_frames = true,
+					}
+				}
+				"--out" => {
+					let out = match &mut parsed.command {
+						Command::Record(RecordArgs { out, .. })
+						| Command::Photo(PhotoArgs { out, .. }) => out,
+						Command::Gui | Command::Bench => {
+							return Err(format!("{arg} only means something with record or photo"))
+						}
+					};
+					*out = Some(value(&mut args, &arg)?.into());
+				}
+				"--camera" => {
+					let value = value(&mut args, &arg)?;
+					parsed.source = Some(match value.as_str() {
+						"test" => SourceId::TestPattern,
+						_ => {
+							let index = value.parse().map_err(|_| {
+								format!("camera should be an index or 'test', not '{value}'")
+							})?;
+							SourceId::Camera(CameraIndex::Index(index))
+						}
+					});
+				}
+				"--input" => {
+					let value = value(&mut args, &arg)?;
+					parsed.source = Some(match value.as_str() {
+						"-" => SourceId::Stdin,
+						_ => SourceId::File(value.into()),
+					});
+				}
+				"--fast" => parsed.fast = true,
+				"--format" => parsed.format = Some(FormatArg::parse(&value(&mut args, &arg)?)?),
+				"--output-dir" => parsed.output_dir = Some(value(&mut args, &arg)?.into()),
+				"--name" => parsed.name = Some(value(&mut args, &arg)?),
+				"--list-cameras" => parsed.list_cameras = true,
+				"--list-formats" => parsed.list_formats = true,
+				"-h" | "--help" => return Ok(None),
+				_ => return Err(format!("unknown argument '{arg}'")),
+			}
+		}
+
+		Ok(Some(parsed))
+	}
+}
+
+fn value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, String> {
+	args.next().ok_or_else(|| format!("{flag} needs a value"))
+}
+
+/// Effects separated by commas, like `pixelate:size=16,colour`. `normal` is
+/// none at all.
+fn parse_effects(s: &str) -> Result<EffectChain, String> {
+	let mut chain = EffectChain::default();
+
+	for item in s.split(',').map(str::trim) {
+		let mut parts = item.split(':');
+		let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+
+		let mut stage = match name.as_str() {
+			"normal" | "none" => continue,
+			"grey" | "gray" => Stage::new(Effect::Trichrome),
+			"colour" | "color" => {
+				let mut stage = Stage::new(Effect::Trichrome);
+				stage.set_param("coloured", ParamValue::Bool(true));
+				stage
+			}
+			_ => match Effect::ALL.iter().find(|e| e.short_name() == name) {
+				Some(&effect) => Stage::new(effect),
+				None => {
+					return Err(format!(
+						"effect should be normal, trichrome, grey, colour, pixelate, grain, or align, not '{name}'"
+					))
+				}
+			},
+		};
+
+		for setting in parts {
+			let (key, value) = setting
+				.split_once('=')
+				.ok_or_else(|| format!("'{setting}' should look like param=value"))?;
+
+			let params = stage.effect.params();
+			let param = params.iter().find(|p| p.name == key).ok_or_else(|| {
+				let names: Vec<_> = params.iter().map(|p| p.name).collect();
+				match names.is_empty() {
+					true => format!("{name} doesn't have any params"),
+					false => format!("{name} doesn't have {key}, try {}", names.join(", ")),
+				}
+			})?;
+
+			stage.set_param(key, param.parse(value)?);
+		}
+
+		chain.stages.push(stage);
+	}
+
+	Ok(chain)
+}
+
+/// Something like `90`, `30s`, `2m`, or `1h30m`. A bare number is seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+	let bad = || format!("duration should look like 30s, 2m, or 1h30m, not '{s}'");
+
+	if let Ok(secs) = s.parse::<f64>() {
+		return Duration::try_from_secs_f64(secs).map_err(|_| bad());
+	}
+
+	let mut total = 0.0;
+	let mut rest = s;
+	if rest.is_empty() {
+		return Err(bad());
+	}
+	while !rest.is_empty() {
+		let end = rest
+			.find(|c: char| !(c.is_ascii_digit() || c == '.'))
+			.ok_or_else(bad)?;
+		let (number, unit) = rest.split_at(end);
+		let number: f64 = number.parse().map_err(|_| bad())?;
+
+		let mut unit_chars = unit.chars();
+		let scale = match unit_chars.next() {
+			Some('h') => 3600.0,
+			Some('m') => 60.0,
+			Some('s') => 1.0,
+			_ => return Err(bad()),
+		};
+		total += number * scale;
+		rest = unit_chars.as_str();
+	}
+
+	Duration::try_from_secs_f64(total).map_err(|_| bad())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn durations() {
+		for (s, secs) in [
+			("90", 90.0),
+			("0.5", 0.5),
+			("30s", 30.0),
+			("2m", 120.0),
+			("1m30s", 90.0),
+			("1h30m", 5400.0),
+			("0.5s", 0.5),
+		] {
+			assert_eq!(parse_duration(s), Ok(Duration::from_secs_f64(secs)), "{s}");
+		}
+
+		for s in ["", "s", "30x", "1m30", "-5", "1h-2m", "m30s"] {
+			assert!(parse_duration(s).is_err(), "{s} should be an error");
+		}
+	}
+
+	#[test]
+	fn effects() {
+		let mut pixelate = Stage::new(Effect::Pixelate);
+		pixelate.set_param("size", ParamValue::Int(16));
+		let mut colour = Stage::new(Effect::Trichrome);
+		colour.set_param("coloured", ParamValue::Bool(true));
+
+		let chain = parse_effects("pixelate:size=16,colour").unwrap();
+		assert_eq!(chain.stages, [pixelate, colour.clone()]);
+
+		let chain = parse_effects("align, grey ,Grain").unwrap();
+		assert_eq!(
+			chain,
+			EffectChain::new(&[Effect::Align, Effect::Trichrome, Effect::Grain])
+		);
+
+		let chain = parse_effects("trichrome:coloured=true:green_delay=4").unwrap();
+		colour.set_param("green_delay", ParamValue::Int(4));
+		assert_eq!(chain.stages, [colour]);
+
+		assert_eq!(parse_effects("normal").unwrap(), EffectChain::default());
+	}
+
+	#[test]
+	fn bad_effects() {
+		for s in [
+			"sparkles",
+			"pixelate:size",
+			"pixelate:colour=true",
+			"trichrome:coloured=maybe",
+			"grain:amount=lots",
+		] {
+			assert!(parse_effects(s).is_err(), "{s} should be an error");
+		}
+	}
+
+	#[test]
+	fn formats() {
+		assert_eq!(
+			FormatArg::parse("1280x720@30:YUYV"),
+			Ok(FormatArg {
+				width: 1280,
+				height: 720,
+				fps: 30,
+				fourcc: Some(FrameFormat::YUYV),
+			})
+		);
+		assert_eq!(
+			FormatArg::parse("640x480@60"),
+			Ok(FormatArg {
+				width: 640,
+				height: 480,
+				fps: 60,
+				fourcc: None,
+			})
+		);
+		assert_eq!(
+			FormatArg::parse("1920x1080@30:mjpg").map(|f| f.fourcc),
+			Ok(Some(FrameFormat::MJPEG))
+		);
+
+		for s in [
+			"1280x720",
+			"1280@30",
+			"1280x720@thirty",
+			"axb@30",
+			"1280x720@30:H264",
+		] {
+			assert!(FormatArg::parse(s).is_err(), "{s} should be an error");
+		}
+	}
+}
@@ -0,0 +1,73 @@
+use core::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A UTC date and time, to the second. We only need to print these so it's
+/// not worth pulling in a whole date crate.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DateTime {
+	pub year: i64,
+	pub month: u8,
+	pub day: u8,
+	pub hour: u8,
+	pub minute: u8,
+	pub second: u8,
+}
+
+impl DateTime {
+	pub fn now() -> Self {
+		let secs = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+
+		Self::from_unix(secs as i64)
+	}
+
+	pub fn from_unix(secs: i64) -> Self {
+		let days = secs.div_euclid(86400);
+		let rem = secs.rem_euclid(86400);
+
+		// Howard Hinnant's civil_from_days
+		// https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+		let z = days + 719468;
+		let era = z.div_euclid(146097);
+		let doe = z.rem_euclid(146097);
+		let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+		let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+		let mp = (5 * doy + 2) / 153;
+		let day = doy - (153 * mp + 2) / 5 + 1;
+		let month = if mp < 10 { mp + 3 } else { mp - 9 };
+		let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+		Self {
+			year,
+			month: month as u8,
+			day: day as u8,
+			hour: (rem / 3600) as u8,
+			minute: ((rem / 60) % 60) as u8,
+			second: (rem % 60) as u8,
+		}
+	}
+
+	/// YYYY-MM-DD
+	pub fn date(&self) -> String {
+		format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+	}
+
+	/// HH:MM:SS
+	pub fn time(&self) -> String {
+		format!("{:02}:{:02}:{:02}", self.hour, self.minute, self.second)
+	}
+}
+
+/// Format a duration as H:MM:SS, for showing how long we've been recording
+pub fn hms(dur: Duration) -> String {
+	let secs = dur.as_secs();
+	format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+impl fmt::Display for DateTime {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} {} UTC", self.date(), self.time())
+	}
+}
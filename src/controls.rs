@@ -0,0 +1,258 @@
+//! Camera controls, like exposure and white balance, and remembering the ones
+//! you've changed so they're put back next time you open the same camera.
+
+use std::{
+	env,
+	fs::{self, File},
+	io::{self, BufRead, BufReader, Write},
+	path::PathBuf,
+};
+
+use nokhwa::utils::{
+	CameraControl, ControlValueDescription, ControlValueSetter, KnownCameraControl,
+	KnownCameraControlFlag,
+};
+
+use crate::source::FrameSource;
+
+/// Where we keep settings. `$XDG_CONFIG_HOME/trichloride` or
+/// `~/.config/trichloride` on Linux, and the usual places on Windows and
+/// macOS. None if we can't work out where home is.
+pub fn config_dir() -> Option<PathBuf> {
+	let base = if cfg!(target_os = "windows") {
+		env::var_os("APPDATA").map(PathBuf::from)
+	} else if cfg!(target_os = "macos") {
+		env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+	} else {
+		env::var_os("XDG_CONFIG_HOME")
+			.filter(|dir| !dir.is_empty())
+			.map(PathBuf::from)
+			.or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+	};
+
+	Some(base?.join("trichloride"))
+}
+
+/// Can we change it?
+pub fn writable(control: &CameraControl) -> bool {
+	!control.flag().iter().any(|f| {
+		matches!(
+			f,
+			KnownCameraControlFlag::ReadOnly | KnownCameraControlFlag::Disabled
+		)
+	})
+}
+
+/// Things like auto exposure are usually a checkbox or a menu, and they
+/// decide whether the rest can be set at all, so they're set first.
+fn is_mode(control: &CameraControl) -> bool {
+	matches!(
+		control.description(),
+		ControlValueDescription::Boolean { .. } | ControlValueDescription::Enum { .. }
+	)
+}
+
+/// What the control was when the camera started up. None for the kinds we
+/// don't do anything with.
+pub fn default_value(control: &CameraControl) -> Option<ControlValueSetter> {
+	Some(match *control.description() {
+		ControlValueDescription::Integer { default, .. }
+		| ControlValueDescription::IntegerRange { default, .. } => ControlValueSetter::Integer(default),
+		ControlValueDescription::Float { default, .. }
+		| ControlValueDescription::FloatRange { default, .. } => ControlValueSetter::Float(default),
+		ControlValueDescription::Boolean { default, .. } => ControlValueSetter::Boolean(default),
+		ControlValueDescription::Enum { default, .. } => ControlValueSetter::EnumValue(default),
+		_ => return None,
+	})
+}
+
+/// What we call the control in the settings file. Driver specific controls
+/// come through as `Other` with a number, which is as good as a name.
+fn key(control: KnownCameraControl) -> String {
+	format!("{control:?}")
+}
+
+/// The value as it goes in the settings file
+fn value_text(value: &ControlValueSetter) -> Option<String> {
+	match value {
+		ControlValueSetter::Integer(v) | ControlValueSetter::EnumValue(v) => Some(v.to_string()),
+		ControlValueSetter::Float(v) => Some(v.to_string()),
+		ControlValueSetter::Boolean(v) => Some(v.to_string()),
+		_ => None,
+	}
+}
+
+/// Read a value from the settings file as whatever kind of value `control`
+/// takes.
+fn parse_value(control: &CameraControl, text: &str) -> Option<ControlValueSetter> {
+	Some(match control.description() {
+		ControlValueDescription::Integer { .. } | ControlValueDescription::IntegerRange { .. } => {
+			ControlValueSetter::Integer(text.parse().ok()?)
+		}
+		ControlValueDescription::Float { .. } | ControlValueDescription::FloatRange { .. } => {
+			ControlValueSetter::Float(text.parse().ok()?)
+		}
+		ControlValueDescription::Boolean { .. } => ControlValueSetter::Boolean(text.parse().ok()?),
+		ControlValueDescription::Enum { .. } => ControlValueSetter::EnumValue(text.parse().ok()?),
+		_ => return None,
+	})
+}
+
+/// The controls someone's changed on one camera. Only those are saved, so
+/// everything else is left to the camera.
+///
+/// It's a text file of `Control = value` lines in the config directory,
+/// named after the camera.
+pub struct CameraSettings {
+	/// None if there's nowhere to keep it
+	path: Option<PathBuf>,
+	values: Vec<(String, String)>,
+}
+
+impl CameraSettings {
+	/// The settings for the camera called `camera`. Nothing's changed if
+	/// there's no file, or if we can't read it.
+	pub fn load(camera: &str) -> Self {
+		let path = config_dir().map(|dir| dir.join("cameras").join(file_name(camera)));
+		let mut settings = Self {
+			path,
+			values: vec![],
+		};
+
+		let Some(path) = &settings.path else {
+			return settings;
+		};
+
+		let file = match File::open(path) {
+			Ok(file) => file,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return settings,
+			Err(e) => {
+				eprintln!("couldn't read camera settings from {}: {e}", path.display());
+				return settings;
+			}
+		};
+
+		for line in BufReader::new(file).lines().map_while(Result::ok) {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			match line.split_once('=') {
+				Some((key, value)) => settings
+					.values
+					.push((key.trim().to_owned(), value.trim().to_owned())),
+				None => eprintln!("ignoring '{line}' in {}", path.display()),
+			}
+		}
+
+		settings
+	}
+
+	/// Put the saved values back on the camera.
+	pub fn apply(&self, source: &mut dyn FrameSource) {
+		if self.values.is_empty() {
+			return;
+		}
+
+		// Look again after the modes, they change what else can be set
+		for modes in [true, false] {
+			for control in source.controls() {
+				if is_mode(&control) != modes || !writable(&control) {
+					continue;
+				}
+
+				let key = key(control.control());
+				let Some((_, text)) = self.values.iter().find(|(k, _)| *k == key) else {
+					continue;
+				};
+
+				match parse_value(&control, text) {
+					Some(value) => {
+						if let Err(e) = source.set_control(control.control(), value) {
+							eprintln!("couldn't set {} to {text}: {e}", control.name());
+						}
+					}
+					None => eprintln!("'{text}' doesn't make sense for {}", control.name()),
+				}
+			}
+		}
+	}
+
+	/// Remember that `control` was set to `value`.
+	pub fn set(&mut self, control: KnownCameraControl, value: &ControlValueSetter) {
+		let Some(text) = value_text(value) else {
+			return;
+		};
+		let key = key(control);
+
+		match self.values.iter_mut().find(|(k, _)| *k == key) {
+			Some((_, old)) => *old = text,
+			None => self.values.push((key, text)),
+		}
+	}
+
+	/// Forget everything, so the camera's left how it likes it.
+	pub fn clear(&mut self) {
+		self.values.clear();
+	}
+
+	pub fn save(&self) -> io::Result<()> {
+		let Some(path) = &self.path else {
+			return Ok(());
+		};
+
+		if self.values.is_empty() {
+			return match fs::remove_file(path) {
+				Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+				_ => Ok(()),
+			};
+		}
+
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)?;
+		}
+
+		let mut file = File::create(path)?;
+		writeln!(
+			file,
+			"# Camera controls trichloride puts back when it opens this camera"
+		)?;
+		for (key, value) in &self.values {
+			writeln!(file, "{key} = {value}")?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Camera names can have all sorts in them, so keep the letters and numbers.
+fn file_name(camera: &str) -> String {
+	let name: String = camera
+		.trim()
+		.chars()
+		.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+		.collect();
+
+	match name.is_empty() {
+		true => String::from("camera.txt"),
+		false => format!("{name}.txt"),
+	}
+}
+
+/// Set every control back to its default.
+pub fn reset(source: &mut dyn FrameSource) {
+	for modes in [true, false] {
+		for control in source.controls() {
+			if is_mode(&control) != modes || !writable(&control) {
+				continue;
+			}
+
+			if let Some(value) = default_value(&control) {
+				if let Err(e) = source.set_control(control.control(), value) {
+					eprintln!("couldn't reset {}: {e}", control.name());
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,263 @@
+use std::{
+	fs,
+	io::{self, Write},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		mpsc::{channel, RecvTimeoutError},
+		Arc,
+	},
+	time::{Duration, Instant},
+};
+
+use eframe::egui;
+use nokhwa::utils::CameraFormat;
+
+use crate::{
+	capture::CameraThread,
+	cli::{PhotoArgs, RecordArgs},
+	clock::hms,
+	output::{self, OutputSettings},
+	photo::PhotoRequest,
+	source::{Pacing, SourceId},
+	Cl3Events,
+};
+
+/// How often we print how it's going
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Record without a window. Stops after the duration, when the source runs
+/// out, or on Ctrl-C, and always finishes the MP4 properly. Returns the
+/// exit code.
+pub fn record(
+	source: SourceId,
+	format: Option<CameraFormat>,
+	pacing: Pacing,
+	mut settings: OutputSettings,
+	args: RecordArgs,
+) -> i32 {
+	let created = match &args.out {
+		Some(path) => output::create_new(path),
+		None => settings.create(&args.effects),
+	};
+	let out = match created {
+		Ok(out) => out,
+		Err(e) => {
+			let path = args.out.unwrap_or(settings.dir);
+			eprintln!(
+				"couldn't make a file to record into at {}: {e}",
+				path.display()
+			);
+			return 1;
+		}
+	};
+	let path = out.path.clone();
+
+	let interrupted = Arc::new(AtomicBool::new(false));
+	{
+		let interrupted = interrupted.clone();
+		let handler = ctrlc::set_handler(move || {
+			// Ctrl-C twice if finishing up is taking too long
+			if interrupted.swap(true, Ordering::SeqCst) {
+				std::process::exit(130);
+			}
+		});
+
+		if let Err(e) = handler {
+			eprintln!("couldn't set the Ctrl-C handler, you won't be able to stop early: {e}");
+		}
+	}
+
+	// Nothing's ever drawn with this, the camera thread just wants something
+	// to wake up
+	let ctx: egui::Context = Default::default();
+	let (tx, rx) = channel();
+	let mut camera = CameraThread::new(tx, source, format, pacing);
+
+	camera.start_recording(ctx, out);
+	camera.set_effects(args.effects.clone());
+
+	println!("Recording {} to {}", args.effects, path.display());
+	let mut last_progress = Instant::now();
+	let mut failed = false;
+	let reason = loop {
+		camera.reap();
+
+		match rx.recv_timeout(Duration::from_millis(100)) {
+			Ok(
+				Cl3Events::FrameReceive
+				| Cl3Events::CameraReconnected
+				| Cl3Events::PhotoSaved(_)
+				| Cl3Events::PhotoError(_),
+			)
+			| Err(RecvTimeoutError::Timeout) => (),
+			Ok(Cl3Events::SourceFinished) => break "the input ran out",
+			// There's no-one to wait for it with, so keep what we've got
+			Ok(Cl3Events::CameraDisconnected) => {
+				failed = true;
+				break "the camera was disconnected";
+			}
+			Ok(Cl3Events::CameraError(e)) | Ok(Cl3Events::RecordingError(e)) => {
+				eprintln!("\n{e}");
+				failed = true;
+				break "something went wrong";
+			}
+			Err(RecvTimeoutError::Disconnected) => break "the camera thread went away",
+		}
+
+		if interrupted.load(Ordering::SeqCst) {
+			break "interrupted";
+		}
+
+		let stats = camera.record_stats();
+		if let (Some(stats), Some(duration)) = (&stats, args.duration) {
+			if stats.duration >= duration {
+				break "done";
+			}
+		}
+
+		if last_progress.elapsed() >= PROGRESS_INTERVAL {
+			last_progress = Instant::now();
+
+			if let Some(stats) = stats {
+				print!(
+					"\rREC {} · {} frames · {} dropped · {:.0} kbps   ",
+					hms(stats.duration),
+					stats.frames,
+					camera.dropped().total(),
+					stats.bitrate_kbps()
+				);
+				io::stdout().flush().ok();
+			}
+		}
+	};
+
+	// Stopping the encoder is what writes the end of the MP4
+	println!("\nStopping, {reason}. Finishing {}...", path.display());
+	camera.stop();
+
+	// Anything that went wrong while we were finishing up
+	for event in rx.try_iter() {
+		if let Cl3Events::CameraError(e) | Cl3Events::RecordingError(e) = event {
+			eprintln!("{e}");
+			failed = true;
+		}
+	}
+
+	match camera.record_stats() {
+		Some(stats) if stats.frames > 0 => {
+			let dropped = camera.dropped();
+			println!(
+				"Wrote {} frames ({}) to {}",
+				stats.frames,
+				hms(stats.duration),
+				path.display()
+			);
+
+			if dropped.total() > 0 {
+				println!(
+					"Dropped {} frames, {} because the encoder was behind and {} the source sent broken",
+					dropped.total(),
+					dropped.encoder,
+					dropped.source
+				);
+			}
+
+			match failed {
+				true => 1,
+				false => 0,
+			}
+		}
+		_ => {
+			eprintln!("Didn't record anything");
+			1
+		}
+	}
+}
+
+/// How long we'll wait for the camera to start, and for the photo to save
+/// once the frames are in
+const PHOTO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Take one photo without a window. Returns the exit code.
+pub fn photo(
+	source: SourceId,
+	format: Option<CameraFormat>,
+	pacing: Pacing,
+	mut settings: OutputSettings,
+	args: PhotoArgs,
+) -> i32 {
+	let created = match &args.out {
+		Some(path) => output::create_new(path),
+		None => settings.create_photo(),
+	};
+	let file = match created {
+		Ok(file) => file,
+		Err(e) => {
+			let path = args.out.unwrap_or(settings.dir);
+			eprintln!(
+				"couldn't make a file for the photo at {}: {e}",
+				path.display()
+			);
+			return 1;
+		}
+	};
+	let path = file.path.clone();
+
+	let ctx: egui::Context = Default::default();
+	let (tx, rx) = channel();
+	let mut camera = CameraThread::new(tx, source, format, pacing);
+	camera.start(ctx);
+
+	// Cameras take a moment to get going, and the first frames are often
+	// dark while they work out the exposure, so wait for one
+	let started = match rx.recv_timeout(PHOTO_TIMEOUT) {
+		Ok(Cl3Events::FrameReceive) => Ok(()),
+		Ok(Cl3Events::CameraError(e)) => Err(e),
+		Ok(_) => Err(String::from("the camera stopped before it sent anything")),
+		Err(_) => Err(String::from("the camera didn't send anything")),
+	};
+	if let Err(e) = started {
+		eprintln!("{e}");
+		camera.stop();
+		drop(file);
+		fs::remove_file(&path).ok();
+		return 1;
+	}
+
+	println!(
+		"Taking a photo, three frames {:.1}s apart",
+		args.interval.as_secs_f64()
+	);
+	camera.take_photo(PhotoRequest {
+		file,
+		interval: args.interval,
+		keep_frames: args.keep_frames,
+	});
+
+	let deadline = Instant::now() + args.interval * 2 + PHOTO_TIMEOUT;
+	let result = loop {
+		let wait = deadline.saturating_duration_since(Instant::now());
+		match rx.recv_timeout(wait) {
+			Ok(Cl3Events::PhotoSaved(path)) => break Ok(path),
+			Ok(Cl3Events::PhotoError(e) | Cl3Events::CameraError(e)) => break Err(e),
+			Ok(_) => (),
+			Err(RecvTimeoutError::Timeout) => break Err(String::from("the photo took too long")),
+			Err(RecvTimeoutError::Disconnected) => {
+				break Err(String::from("the camera thread went away"))
+			}
+		}
+	};
+
+	camera.stop();
+
+	match result {
+		Ok(path) => {
+			println!("Saved {}", path.display());
+			0
+		}
+		Err(e) => {
+			eprintln!("{e}");
+			1
+		}
+	}
+}
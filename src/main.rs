@@ -1,72 +1,190 @@
 use std::{
-	sync::mpsc::{channel, Receiver, Sender, TryRecvError},
-	thread::sleep,
+	path::PathBuf,
+	sync::mpsc::{channel, Receiver},
 	time::Duration,
 };
 
-use capture::{CameraEvent, CameraThread, Effect};
+use capture::{CameraThread, Effect, EffectChain, Stage};
+use cli::{Args, Command};
+use clock::hms;
 use eframe::{
 	egui::{self, CentralPanel, Margin, TextureOptions, TopBottomPanel, ViewportBuilder},
 	epaint::{Color32, ColorImage, Rounding, Shadow, Stroke, TextureHandle, Vec2},
 };
-use nokhwa::{nokhwa_check, nokhwa_initialize, utils::ApiBackend};
+use nokhwa::{
+	nokhwa_check, nokhwa_initialize,
+	utils::{
+		CameraControl, CameraFormat, CameraInfo, ControlValueDescription, ControlValueSetter,
+		FrameFormat, Resolution,
+	},
+};
+use output::OutputSettings;
+use photo::PhotoRequest;
+use source::{Pacing, SourceId};
+use vex::{Param, ParamKind, ParamValue};
 
+mod bench;
 mod capture;
-mod nv12scary;
+mod cli;
+mod clock;
+mod controls;
+mod headless;
+mod output;
+mod photo;
+mod pixfmt;
+mod source;
 mod vex;
 
 fn main() -> Result<(), eframe::Error> {
+	let args = Args::parse();
 	nokhwa_initialize(|_b| {});
 
-	/*for _ in 0..1 {
-		match nokhwa::query(ApiBackend::AVFoundation) {
-			Err(e) => {
-				println!("query failed! {e}");
+	if args.list_cameras {
+		println!("Cameras:");
+		for q in source::cameras() {
+			println!("\t{} [{}] {}", q.index(), q.misc(), q.human_name());
+		}
+		return Ok(());
+	}
+
+	let source = args.source.unwrap_or_default();
+	let pacing = match args.fast {
+		true => Pacing::Fast,
+		false => Pacing::Realtime,
+	};
+
+	if args.list_formats {
+		println!("Formats for {source}:");
+		for format in source::formats(&source) {
+			println!("\t{}", capture::format_label(&format));
+		}
+		return Ok(());
+	}
+
+	let format = args.format.map(|arg| {
+		let found = source::formats(&source)
+			.into_iter()
+			.find(|f| arg.matches(f));
+		let resolution = Resolution::new(arg.width, arg.height);
+
+		match (found, arg.fourcc) {
+			(Some(format), _) => format,
+			// Only cameras are picky, the test pattern can be any size and
+			// raw input is whatever you say it is
+			(None, fourcc) if !matches!(source, SourceId::Camera(_)) => {
+				CameraFormat::new(resolution, fourcc.unwrap_or(FrameFormat::RAWRGB), arg.fps)
 			}
-			Ok(query) => {
-				println!("Camera Query:");
-				for q in query {
-					println!("\t{} [{}] {}", q.index(), q.misc(), q.human_name());
-				}
+			// We couldn't see it in the list, but if we know everything we can
+			// still ask the camera for it.
+			(None, Some(fourcc)) => CameraFormat::new(resolution, fourcc, arg.fps),
+			(None, None) => {
+				eprintln!(
+					"{source} doesn't have a {}x{}@{} format, see --list-formats",
+					arg.width, arg.height, arg.fps
+				);
+				std::process::exit(1);
 			}
-		};
+		}
+	});
+
+	let mut output = OutputSettings::default();
+	if let Some(dir) = args.output_dir {
+		output.dir = dir;
+	}
+	if let Some(name) = args.name {
+		output.template = name;
+	}
 
-		//println!("sleeping for a second!");
-		sleep(Duration::from_secs(1));
-	}*/
+	match args.command {
+		Command::Record(record) => {
+			std::process::exit(headless::record(source, format, pacing, output, record))
+		}
+		Command::Photo(photo) => {
+			std::process::exit(headless::photo(source, format, pacing, output, photo))
+		}
+		Command::Bench => {
+			bench::run();
+			return Ok(());
+		}
+		Command::Gui => (),
+	}
 
 	let options = eframe::NativeOptions {
 		viewport: ViewportBuilder::default().with_inner_size((640.0, 434.0)),
 		..Default::default()
 	};
 
-	eframe::run_native("trichloride", options, Box::new(|_cc| Box::new(App::new())))
+	eframe::run_native(
+		"trichloride",
+		options,
+		Box::new(move |_cc| Box::new(App::new(source, format, pacing, output))),
+	)
 }
 
 enum Cl3Events {
 	FrameReceive,
+	/// The file or stream we were reading has run out
+	SourceFinished,
+	/// The camera thread couldn't open the source, or stopped because of a
+	/// problem with it
+	CameraError(String),
+	/// Something went wrong with the recording, and it's been stopped
+	RecordingError(String),
+	/// The camera's stopped giving us frames, probably because it was unplugged.
+	/// We'll keep looking for it.
+	CameraDisconnected,
+	/// It's back, and the preview's going again
+	CameraReconnected,
+	/// The photo's been put together and saved here
+	PhotoSaved(PathBuf),
+	/// We couldn't take the photo, or couldn't save it
+	PhotoError(String),
 }
 
 struct App {
 	rx: Receiver<Cl3Events>,
 	preview: Option<TextureHandle>,
-	effect: Effect,
+	effects: EffectChain,
+	cameras: Vec<CameraInfo>,
+	output: OutputSettings,
+	/// The last thing that went wrong, until it's dismissed
+	error: Option<String>,
+	/// The camera's been unplugged and we're waiting for it to come back
+	disconnected: bool,
+	/// How far apart the frames of a photo are
+	photo_interval: Duration,
+	/// Save the frames of a photo as well as the photo
+	keep_photo_frames: bool,
+	/// We've asked for a photo and haven't heard back
+	taking_photo: bool,
+	last_photo: Option<PathBuf>,
 
 	camera_thread: CameraThread,
-	camera_sender: Sender<CameraEvent>,
 }
 
 impl App {
-	fn new() -> Self {
+	fn new(
+		source: SourceId,
+		format: Option<CameraFormat>,
+		pacing: Pacing,
+		output: OutputSettings,
+	) -> Self {
 		let (tx, rx) = channel();
-		let camera = CameraThread::new(tx);
+		let camera = CameraThread::new(tx, source, format, pacing);
 
 		Self {
 			rx,
 			preview: None,
-			effect: Effect::Normal,
+			effects: EffectChain::default(),
+			cameras: source::cameras(),
+			output,
+			error: None,
+			disconnected: false,
+			photo_interval: photo::DEFAULT_INTERVAL,
+			keep_photo_frames: false,
+			taking_photo: false,
+			last_photo: None,
 
-			camera_sender: camera.camera_tx(),
 			camera_thread: camera,
 		}
 	}
@@ -106,34 +224,447 @@ impl App {
 	}
 
 	fn start_preview(&mut self, ctx: &egui::Context) {
+		self.error = None;
+		self.disconnected = false;
 		self.camera_thread.start(ctx.clone());
 	}
 
 	fn stop_preview(&mut self) {
+		self.disconnected = false;
 		self.camera_thread.stop();
 	}
 
+	fn camera_selection(&mut self, ui: &mut egui::Ui) {
+		let current = self.camera_thread.source().clone();
+		let mut selected = current.clone();
+		let mut refresh = false;
+
+		let current_name = match &current {
+			SourceId::Camera(index) => self
+				.cameras
+				.iter()
+				.find(|c| c.index() == index)
+				.map(|c| c.human_name())
+				.unwrap_or_else(|| format!("Camera {index}")),
+			SourceId::TestPattern => String::from("Test pattern"),
+			// These only come from the command line
+			SourceId::File(_) | SourceId::Stdin => current.to_string(),
+		};
+
+		ui.label("Camera");
+		ui.add_enabled_ui(!self.camera_thread.recording(), |ui| {
+			egui::ComboBox::from_id_source("camera_selection")
+				.width(150.0)
+				.selected_text(current_name)
+				.show_ui(ui, |ui| {
+					for camera in &self.cameras {
+						ui.selectable_value(
+							&mut selected,
+							SourceId::Camera(camera.index().clone()),
+							camera.human_name(),
+						);
+					}
+					ui.selectable_value(&mut selected, SourceId::TestPattern, "Test pattern");
+				});
+
+			refresh = ui.button("⟳").on_hover_text("Refresh cameras").clicked();
+		});
+
+		if refresh {
+			self.cameras = source::cameras();
+		}
+
+		if selected != current {
+			self.camera_thread.set_source(selected, ui.ctx().clone());
+		}
+	}
+
+	fn format_selection(&mut self, ui: &mut egui::Ui) {
+		let current = self.camera_thread.requested_format();
+		let mut selected = current;
+
+		let label = |format: Option<CameraFormat>| match format {
+			Some(format) => capture::format_label(&format),
+			None => String::from("Highest resolution"),
+		};
+
+		ui.label("Format");
+		ui.add_enabled_ui(!self.camera_thread.recording(), |ui| {
+			egui::ComboBox::from_id_source("format_selection")
+				.width(175.0)
+				.selected_text(label(current))
+				.show_ui(ui, |ui| {
+					ui.selectable_value(&mut selected, None, label(None));
+
+					let formats = self.camera_thread.formats();
+					if formats.is_empty() {
+						ui.label("Start the preview to see formats");
+					}
+
+					for format in formats {
+						ui.selectable_value(&mut selected, Some(format), label(Some(format)));
+					}
+				});
+		});
+
+		if selected != current {
+			self.camera_thread.set_format(selected, ui.ctx().clone());
+		}
+	}
+
 	fn start_recording(&mut self, ctx: &egui::Context) {
-		self.camera_thread.start_recording(ctx.clone());
+		match self.output.create(&self.effects) {
+			Ok(file) => {
+				self.error = None;
+				self.camera_thread.start_recording(ctx.clone(), file);
+			}
+			Err(e) => self.error = Some(format!("Couldn't start recording: {e}")),
+		}
+	}
+
+	fn output_settings(&mut self, ui: &mut egui::Ui) {
+		let mut dir = self.output.dir.display().to_string();
+
+		ui.add_enabled_ui(!self.camera_thread.recording(), |ui| {
+			ui.label("Save to");
+			if ui
+				.add(egui::TextEdit::singleline(&mut dir).desired_width(150.0))
+				.changed()
+			{
+				self.output.dir = dir.into();
+			}
+
+			ui.label("Name");
+			ui.add(egui::TextEdit::singleline(&mut self.output.template).desired_width(250.0))
+				.on_hover_text("{date}, {time}, {effect}, and {take} are filled in");
+		});
+
+		if let Some(path) = self.camera_thread.last_recording() {
+			let reveal = ui
+				.button("Reveal last recording")
+				.on_hover_text(path.display().to_string());
+
+			if reveal.clicked() {
+				if let Err(e) = output::reveal(path) {
+					self.error = Some(format!("Couldn't open the file manager: {e}"));
+				}
+			}
+		}
 	}
 
 	fn stop_recording(&mut self) {
 		self.camera_thread.stop_recording();
 	}
+
+	fn take_photo(&mut self) {
+		match self.output.create_photo() {
+			Ok(file) => {
+				self.error = None;
+				self.taking_photo = true;
+				self.camera_thread.take_photo(PhotoRequest {
+					file,
+					interval: self.photo_interval,
+					keep_frames: self.keep_photo_frames,
+				});
+			}
+			Err(e) => self.error = Some(format!("Couldn't take a photo: {e}")),
+		}
+	}
+
+	fn photo_settings(&mut self, ui: &mut egui::Ui) {
+		let enabled = self.camera_thread.running() && !self.taking_photo;
+		let label = match self.taking_photo {
+			true => "Taking photo…",
+			false => "Take photo",
+		};
+
+		if ui
+			.add_enabled(enabled, egui::Button::new(label))
+			.on_hover_text("Three frames a little while apart, one for each colour")
+			.clicked()
+		{
+			self.take_photo();
+		}
+
+		let mut secs = self.photo_interval.as_secs_f64();
+		ui.label("Interval");
+		if ui
+			.add(
+				egui::DragValue::new(&mut secs)
+					.speed(0.05)
+					.clamp_range(0.0..=10.0)
+					.suffix("s"),
+			)
+			.changed()
+		{
+			self.photo_interval = Duration::from_secs_f64(secs);
+		}
+
+		ui.checkbox(&mut self.keep_photo_frames, "Save the three frames too");
+
+		if let Some(path) = &self.last_photo {
+			let reveal = ui
+				.button("Reveal last photo")
+				.on_hover_text(path.display().to_string());
+
+			if reveal.clicked() {
+				if let Err(e) = output::reveal(path) {
+					self.error = Some(format!("Couldn't open the file manager: {e}"));
+				}
+			}
+		}
+	}
+
+	fn effect_chain(&mut self, ui: &mut egui::Ui) {
+		let mut effects = self.effects.clone();
+
+		egui::CollapsingHeader::new(format!("Effects: {}", self.effects))
+			.id_source("effects")
+			.show(ui, |ui| {
+				let count = effects.stages.len();
+				let mut move_down = None;
+				let mut remove = None;
+
+				for (idx, stage) in effects.stages.iter_mut().enumerate() {
+					ui.horizontal(|ui| {
+						let mut on = !stage.bypass;
+						if ui
+							.checkbox(&mut on, stage.effect.to_string())
+							.on_hover_text("Untick to bypass it")
+							.changed()
+						{
+							stage.bypass = !on;
+						}
+
+						if ui
+							.add_enabled(idx > 0, egui::Button::new("⬆").small())
+							.clicked()
+						{
+							move_down = Some(idx - 1);
+						}
+						if ui
+							.add_enabled(idx + 1 < count, egui::Button::new("⬇").small())
+							.clicked()
+						{
+							move_down = Some(idx);
+						}
+						if ui.small_button("✕").clicked() {
+							remove = Some(idx);
+						}
+
+						let params = stage.effect.params();
+						for (param, value) in params.iter().zip(stage.params.iter_mut()) {
+							param_widget(ui, param, value);
+						}
+					});
+				}
+
+				if let Some(idx) = move_down {
+					effects.stages.swap(idx, idx + 1);
+				}
+				if let Some(idx) = remove {
+					effects.stages.remove(idx);
+				}
+
+				let mut add = None;
+				egui::ComboBox::from_id_source("add_effect")
+					.selected_text("Add effect")
+					.show_ui(ui, |ui| {
+						for effect in Effect::ALL {
+							if ui.selectable_label(false, effect.to_string()).clicked() {
+								add = Some(effect);
+							}
+						}
+					});
+
+				if let Some(effect) = add {
+					effects.stages.push(Stage::new(effect));
+				}
+			});
+
+		if effects != self.effects {
+			self.effects = effects;
+			self.camera_thread.set_effects(self.effects.clone());
+		}
+	}
+
+	fn camera_controls(&mut self, ui: &mut egui::Ui) {
+		let controls = self.camera_thread.controls();
+		if controls.is_empty() {
+			return;
+		}
+
+		ui.collapsing("Camera controls", |ui| {
+			egui::Grid::new("camera_controls")
+				.num_columns(2)
+				.show(ui, |ui| {
+					for control in &controls {
+						ui.label(control.name());
+
+						let changed = ui
+							.add_enabled_ui(controls::writable(control), |ui| {
+								control_widget(ui, control)
+							})
+							.inner;
+						if let Some(value) = changed {
+							self.camera_thread.set_control(control.control(), value);
+						}
+
+						ui.end_row();
+					}
+				});
+
+			if ui
+				.button("Reset to defaults")
+				.on_hover_text(
+					"Put everything back how the camera had it, and forget what we saved",
+				)
+				.clicked()
+			{
+				self.camera_thread.reset_controls();
+			}
+		});
+	}
+}
+
+/// A checkbox or a slider for one of an effect's params.
+fn param_widget(ui: &mut egui::Ui, param: &Param, value: &mut ParamValue) {
+	match param.kind {
+		ParamKind::Bool { .. } => {
+			let mut on = value.as_bool();
+			ui.checkbox(&mut on, param.label);
+			*value = ParamValue::Bool(on);
+		}
+		ParamKind::Int { min, max, .. } => {
+			let mut v = value.as_int();
+			ui.add(egui::Slider::new(&mut v, min..=max).text(param.label));
+			*value = ParamValue::Int(v);
+		}
+		ParamKind::Float { min, max, .. } => {
+			let mut v = value.as_float();
+			ui.add(egui::Slider::new(&mut v, min..=max).text(param.label));
+			*value = ParamValue::Float(v);
+		}
+		ParamKind::Choice { options, .. } => {
+			let mut v = value.as_int() as usize;
+			ui.label(param.label);
+			egui::ComboBox::from_id_source(ui.make_persistent_id(param.name))
+				.selected_text(param.display(*value))
+				.show_ui(ui, |ui| {
+					for (idx, option) in options.iter().enumerate() {
+						ui.selectable_value(&mut v, idx, *option);
+					}
+				});
+			*value = ParamValue::Int(v as i32);
+		}
+	}
+}
+
+/// A slider or checkbox or whatever suits the control. Returns the new value
+/// if it was changed.
+fn control_widget(ui: &mut egui::Ui, control: &CameraControl) -> Option<ControlValueSetter> {
+	match *control.description() {
+		ControlValueDescription::IntegerRange {
+			min,
+			max,
+			mut value,
+			step,
+			..
+		} => ui
+			.add(egui::Slider::new(&mut value, min..=max).step_by(step.max(1) as f64))
+			.changed()
+			.then_some(ControlValueSetter::Integer(value)),
+		ControlValueDescription::Integer {
+			mut value, step, ..
+		} => ui
+			.add(egui::DragValue::new(&mut value).speed(step.max(1) as f64))
+			.changed()
+			.then_some(ControlValueSetter::Integer(value)),
+		ControlValueDescription::FloatRange {
+			min,
+			max,
+			mut value,
+			step,
+			..
+		} => ui
+			.add(egui::Slider::new(&mut value, min..=max).step_by(step.max(0.0)))
+			.changed()
+			.then_some(ControlValueSetter::Float(value)),
+		ControlValueDescription::Float {
+			mut value, step, ..
+		} => ui
+			.add(egui::DragValue::new(&mut value).speed(step.max(0.01)))
+			.changed()
+			.then_some(ControlValueSetter::Float(value)),
+		ControlValueDescription::Boolean { mut value, .. } => ui
+			.checkbox(&mut value, "")
+			.changed()
+			.then_some(ControlValueSetter::Boolean(value)),
+		// The camera doesn't tell us what the options mean, so numbers will
+		// have to do
+		ControlValueDescription::Enum {
+			value,
+			ref possible,
+			..
+		} => {
+			let mut selected = value;
+			egui::ComboBox::from_id_source(("camera_control", control.name()))
+				.selected_text(value.to_string())
+				.show_ui(ui, |ui| {
+					for option in possible {
+						ui.selectable_value(&mut selected, *option, option.to_string());
+					}
+				});
+
+			(selected != value).then_some(ControlValueSetter::EnumValue(selected))
+		}
+		_ => {
+			ui.weak("can't change this here");
+			None
+		}
+	}
 }
 
 impl eframe::App for App {
 	fn update(&mut self, ctx: &eframe::egui::Context, eframe: &mut eframe::Frame) {
-		match self.rx.try_recv() {
-			Ok(Cl3Events::FrameReceive) => {
-				if let Some(preview) = self.preview.as_mut() {
-					let lock = self.camera_thread.frame();
-					let clrimg = ColorImage::from_rgb([lock.width, lock.height], &lock.data);
-					preview.set(clrimg, TextureOptions::default());
+		self.camera_thread.reap();
+
+		let mut new_frame = false;
+		while let Ok(event) = self.rx.try_recv() {
+			match event {
+				Cl3Events::FrameReceive => new_frame = true,
+				Cl3Events::SourceFinished => self.stop_recording(),
+				// Without a camera there's nothing to record, so finish what
+				// we've got
+				Cl3Events::CameraError(e) | Cl3Events::RecordingError(e) => {
+					eprintln!("{e}");
+					self.error = Some(e);
+					self.stop_recording();
+				}
+				// Finish the recording while we've still got a good file
+				Cl3Events::CameraDisconnected => {
+					self.disconnected = true;
+					self.stop_recording();
+				}
+				Cl3Events::CameraReconnected => self.disconnected = false,
+				Cl3Events::PhotoSaved(path) => {
+					self.taking_photo = false;
+					self.last_photo = Some(path);
+				}
+				Cl3Events::PhotoError(e) => {
+					eprintln!("{e}");
+					self.taking_photo = false;
+					self.error = Some(e);
 				}
 			}
-			Err(TryRecvError::Disconnected) => unreachable!(),
-			Err(TryRecvError::Empty) => {}
+		}
+
+		if new_frame {
+			if let Some(preview) = self.preview.as_mut() {
+				let lock = self.camera_thread.frame();
+				let clrimg = ColorImage::from_rgb([lock.width, lock.height], &lock.data);
+				preview.set(clrimg, TextureOptions::default());
+			}
 		}
 
 		let central = egui::Frame {
@@ -182,35 +713,52 @@ impl eframe::App for App {
 								self.start_recording(ctx);
 							}
 
-							let mut selected_effect = self.effect;
-							ui.label("Effect");
-							egui::ComboBox::from_id_source("effect_selection")
-								.width(125.0)
-								.selected_text(selected_effect.to_string())
-								.show_ui(ui, |ui| {
-									ui.selectable_value(
-										&mut selected_effect,
-										Effect::Normal,
-										Effect::Normal.to_string(),
-									);
-									ui.selectable_value(
-										&mut selected_effect,
-										Effect::TricrideoGrey,
-										Effect::TricrideoGrey.to_string(),
-									);
-									ui.selectable_value(
-										&mut selected_effect,
-										Effect::TricrideoColour,
-										Effect::TricrideoColour.to_string(),
-									)
-								});
-
-							if selected_effect != self.effect {
-								self.effect = selected_effect;
-								self.camera_sender
-									.send(CameraEvent::ChangeEffect(selected_effect));
-							}
+							self.camera_selection(ui);
+							self.format_selection(ui);
 						});
+
+						self.effect_chain(ui);
+						ui.horizontal(|ui| self.output_settings(ui));
+						ui.horizontal(|ui| self.photo_settings(ui));
+						self.camera_controls(ui);
+
+						if self.disconnected {
+							ui.colored_label(
+								Color32::YELLOW,
+								"Camera disconnected, waiting for it to come back…",
+							);
+						}
+
+						if let Some(error) = &self.error {
+							let mut dismissed = false;
+							ui.horizontal(|ui| {
+								dismissed = ui.small_button("✕").clicked();
+								ui.colored_label(Color32::LIGHT_RED, error);
+							});
+
+							if dismissed {
+								self.error = None;
+							}
+						}
+
+						if self.camera_thread.recording() {
+							if let Some(stats) = self.camera_thread.record_stats() {
+								let dropped = self.camera_thread.dropped();
+
+								ui.label(format!(
+									"REC {} · {} frames · {} dropped · {:.0} kbps · {:.1}ms/frame",
+									hms(stats.duration),
+									stats.frames,
+									dropped.total(),
+									stats.bitrate_kbps(),
+									stats.average_encode_time().as_secs_f64() * 1000.0
+								))
+								.on_hover_text(format!(
+									"{} while the encoder was behind, {} the camera sent broken",
+									dropped.encoder, dropped.source
+								));
+							}
+						}
 					});
 			});
 
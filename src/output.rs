@@ -0,0 +1,188 @@
+use std::{
+	fs::{self, File, OpenOptions},
+	io::{self, ErrorKind},
+	path::{Path, PathBuf},
+	process::Command,
+	thread,
+};
+
+use crate::{capture::EffectChain, clock::DateTime};
+
+/// What recordings are called if you don't say otherwise
+pub const DEFAULT_TEMPLATE: &str = "trichloride-{date}-{time}-{effect}-{take}.mp4";
+
+/// We'll look this far past the take number for a name that isn't taken
+/// before giving up.
+const MAX_TAKES: u32 = 10_000;
+
+/// Where recordings go and what they're called.
+///
+/// The name is a template. `{date}` is YYYY-MM-DD, `{time}` is HH-MM-SS (in
+/// UTC, and with dashes because Windows won't have colons in filenames),
+/// `{effect}` is the effects we started recording with, and `{take}` counts up
+/// from 001 each time we record.
+#[derive(Clone, Debug)]
+pub struct OutputSettings {
+	pub dir: PathBuf,
+	pub template: String,
+	/// The next take number
+	pub take: u32,
+}
+
+impl Default for OutputSettings {
+	fn default() -> Self {
+		Self {
+			dir: PathBuf::from("."),
+			template: String::from(DEFAULT_TEMPLATE),
+			take: 1,
+		}
+	}
+}
+
+/// A file we just made to record into
+pub struct NewFile {
+	pub path: PathBuf,
+	pub file: File,
+}
+
+impl OutputSettings {
+	/// Make the file for the next recording. If the name we come up with is
+	/// already there we keep counting the take up until it isn't, or add a
+	/// number on the end if there's no `{take}` in the template. We never
+	/// overwrite anything.
+	pub fn create(&mut self, effects: &EffectChain) -> io::Result<NewFile> {
+		self.create_named(&effects.short_name(), None)
+	}
+
+	/// Make the file for a photo. It's named like a recording with `photo`
+	/// for the effect, and it's always a PNG.
+	pub fn create_photo(&mut self) -> io::Result<NewFile> {
+		self.create_named("photo", Some("png"))
+	}
+
+	fn create_named(&mut self, effect: &str, extension: Option<&str>) -> io::Result<NewFile> {
+		fs::create_dir_all(&self.dir)?;
+		let now = DateTime::now();
+
+		let has_take = self.template.contains("{take}");
+
+		for attempt in 0..MAX_TAKES {
+			let mut name = expand(&self.template, now, effect, self.take)
+				.map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+			if let Some(extension) = extension {
+				name = with_extension(&name, extension);
+			}
+			self.take += 1;
+
+			let name = match attempt {
+				0 => name,
+				_ if has_take => name,
+				_ => numbered(&name, attempt),
+			};
+
+			match create_new(self.dir.join(name)) {
+				Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+				result => return result,
+			}
+		}
+
+		Err(io::Error::new(
+			ErrorKind::AlreadyExists,
+			format!("couldn't find a free name for {}", self.template),
+		))
+	}
+}
+
+/// Make the file at `path`, but only if there's nothing there already.
+pub fn create_new<P: Into<PathBuf>>(path: P) -> io::Result<NewFile> {
+	let path = path.into();
+	let file = OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(&path)?;
+
+	Ok(NewFile { path, file })
+}
+
+/// Fill in the template. Errors if there's a `{` that doesn't start something
+/// we know about.
+fn expand(template: &str, now: DateTime, effect: &str, take: u32) -> Result<String, String> {
+	let mut name = String::new();
+	let mut rest = template;
+
+	while let Some(start) = rest.find('{') {
+		name.push_str(&rest[..start]);
+		rest = &rest[start..];
+
+		let end = rest
+			.find('}')
+			.ok_or_else(|| format!("the {{ in '{template}' isn't closed"))?;
+		match &rest[1..end] {
+			"date" => name.push_str(&now.date()),
+			"time" => name.push_str(&format!(
+				"{:02}-{:02}-{:02}",
+				now.hour, now.minute, now.second
+			)),
+			"effect" => name.push_str(effect),
+			"take" => name.push_str(&format!("{take:03}")),
+			other => {
+				return Err(format!(
+					"don't know what {{{other}}} is, try date, time, effect, or take"
+				))
+			}
+		}
+		rest = &rest[end + 1..];
+	}
+	name.push_str(rest);
+
+	if name.contains(['/', '\\']) {
+		return Err(format!(
+			"'{template}' should just be a name, put directories in the output directory"
+		));
+	}
+
+	Ok(name)
+}
+
+/// `clip.mp4` -> `clip.{extension}`
+fn with_extension(name: &str, extension: &str) -> String {
+	match name.rsplit_once('.') {
+		Some((stem, _)) if !stem.is_empty() => format!("{stem}.{extension}"),
+		_ => format!("{name}.{extension}"),
+	}
+}
+
+/// `clip.mp4` -> `clip-{n}.mp4`
+fn numbered(name: &str, n: u32) -> String {
+	match name.rsplit_once('.') {
+		Some((stem, ext)) if !stem.is_empty() => format!("{stem}-{n}.{ext}"),
+		_ => format!("{name}-{n}"),
+	}
+}
+
+/// Open the file manager at `path`, with it selected if we can.
+pub fn reveal(path: &Path) -> io::Result<()> {
+	let mut command = if cfg!(target_os = "macos") {
+		let mut command = Command::new("open");
+		command.arg("-R").arg(path);
+		command
+	} else if cfg!(target_os = "windows") {
+		let mut command = Command::new("explorer");
+		command.arg(format!("/select,{}", path.display()));
+		command
+	} else {
+		// xdg-open can't select things, so the best we can do is the directory
+		let dir = match path.parent() {
+			Some(dir) if !dir.as_os_str().is_empty() => dir,
+			_ => Path::new("."),
+		};
+		let mut command = Command::new("xdg-open");
+		command.arg(dir);
+		command
+	};
+
+	// Wait for it somewhere else so it doesn't hang around as a zombie
+	let mut child = command.spawn()?;
+	thread::spawn(move || child.wait());
+	Ok(())
+}
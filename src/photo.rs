@@ -0,0 +1,132 @@
+//! Trichrome stills, like dslr-trichrome makes. Three frames a little while
+//! apart go into red, green, and blue, and the result is saved as a PNG.
+
+use std::{
+	fs,
+	io::{BufWriter, Write},
+	path::{Path, PathBuf},
+	sync::mpsc::Sender,
+	thread,
+	time::{Duration, Instant},
+};
+
+use image::{codecs::png::PngEncoder, ColorType, ImageEncoder};
+
+use crate::{
+	capture::{BorrowedFrame, Frame},
+	output::{self, NewFile},
+	vex::{Tricrideo, Vex},
+	Cl3Events,
+};
+
+/// How far apart the three frames are if you don't say
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A photo someone's asked for
+pub struct PhotoRequest {
+	/// Where the photo goes, likely from
+	/// [OutputSettings::create_photo](crate::output::OutputSettings::create_photo)
+	pub file: NewFile,
+	/// Time between the three frames
+	pub interval: Duration,
+	/// Save the three frames as they came from the camera too, next to the
+	/// photo with -r, -g, and -b on the end
+	pub keep_frames: bool,
+}
+
+/// The frames of a photo we're in the middle of taking
+pub struct Exposures {
+	request: PhotoRequest,
+	frames: Vec<Frame>,
+	/// When we can take the next one
+	next: Option<Instant>,
+}
+
+impl Exposures {
+	pub fn new(request: PhotoRequest) -> Self {
+		Self {
+			request,
+			frames: vec![],
+			next: None,
+		}
+	}
+
+	/// Here's a frame that came in at `captured`, which we keep if it's time
+	/// for the next one. True once we've got all three.
+	pub fn offer(&mut self, frame: BorrowedFrame, captured: Instant) -> bool {
+		if self.next.map(|next| captured >= next).unwrap_or(true) {
+			self.frames.push(frame.to_owned());
+			self.next = Some(captured + self.request.interval);
+		}
+
+		self.frames.len() >= 3
+	}
+
+	/// Put the photo together and save it on another thread, so the camera
+	/// can keep going, then tell the GUI how it went.
+	pub fn develop(self, gui_tx: Sender<Cl3Events>) {
+		thread::spawn(move || {
+			let path = self.request.file.path.clone();
+
+			let event = match self.save() {
+				Ok(()) => Cl3Events::PhotoSaved(path),
+				Err(e) => {
+					fs::remove_file(&path).ok();
+					Cl3Events::PhotoError(format!("Couldn't save {}: {e}", path.display()))
+				}
+			};
+			gui_tx.send(event).ok();
+		});
+	}
+
+	/// We're not going to get the rest of the frames, so don't leave an empty
+	/// file lying around.
+	pub fn abandon(self) {
+		drop(self.request.file.file);
+		fs::remove_file(&self.request.file.path).ok();
+	}
+
+	fn save(self) -> Result<(), String> {
+		let Exposures {
+			request, frames, ..
+		} = self;
+		let first = frames.first().ok_or("there aren't any frames")?;
+		let (width, height) = (first.width, first.height);
+
+		let mut tri = Tricrideo::from_frame(first.borrow().to_owned());
+		tri.set_coloured(true);
+		for frame in &frames {
+			tri.frame_in(frame.borrow());
+		}
+
+		write_png(request.file.file, tri.frame_out().data, width, height)?;
+
+		if request.keep_frames {
+			for (frame, suffix) in frames.iter().zip(["r", "g", "b"]) {
+				let path = suffixed(&request.file.path, suffix);
+				let file = output::create_new(path).map_err(|e| e.to_string())?;
+				write_png(file.file, &frame.data, width, height)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// `photo.png` -> `photo-{suffix}.png`
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+	let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+	let name = match path.extension() {
+		Some(ext) => format!("{stem}-{suffix}.{}", ext.to_string_lossy()),
+		None => format!("{stem}-{suffix}"),
+	};
+	path.with_file_name(name)
+}
+
+fn write_png(file: fs::File, rgb: &[u8], width: usize, height: usize) -> Result<(), String> {
+	let mut writer = BufWriter::new(file);
+	PngEncoder::new(&mut writer)
+		.write_image(rgb, width as u32, height as u32, ColorType::Rgb8)
+		.map_err(|e| e.to_string())?;
+	writer.flush().map_err(|e| e.to_string())
+}
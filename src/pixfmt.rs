@@ -0,0 +1,362 @@
+use core::fmt;
+
+use devout::colorconv::{self, ColorSpace};
+use nokhwa::{
+	pixel_format::RgbFormat,
+	utils::{FrameFormat, Resolution},
+	Buffer,
+};
+
+/// How the bytes of a frame from the camera are laid out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelLayout {
+	Yuyv,
+	Uyvy,
+	Nv12,
+	Mjpeg,
+	Rgb,
+	Gray,
+}
+
+impl PixelLayout {
+	/// What the camera *says* it's sending us.
+	pub fn from_frame_format(format: FrameFormat) -> Self {
+		match format {
+			FrameFormat::MJPEG => PixelLayout::Mjpeg,
+			FrameFormat::YUYV => PixelLayout::Yuyv,
+			FrameFormat::NV12 => PixelLayout::Nv12,
+			FrameFormat::GRAY => PixelLayout::Gray,
+			FrameFormat::RAWRGB => PixelLayout::Rgb,
+		}
+	}
+
+	/// How many bytes a frame should be. None if it's compressed.
+	pub fn frame_len(&self, width: usize, height: usize) -> Option<usize> {
+		match self {
+			PixelLayout::Yuyv | PixelLayout::Uyvy => Some(colorconv::packed422_len(width, height)),
+			PixelLayout::Nv12 => Some(colorconv::yuv420_len(width, height)),
+			PixelLayout::Mjpeg => None,
+			PixelLayout::Rgb => Some(width * height * 3),
+			PixelLayout::Gray => Some(width * height),
+		}
+	}
+}
+
+impl fmt::Display for PixelLayout {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			PixelLayout::Yuyv => write!(f, "YUYV"),
+			PixelLayout::Uyvy => write!(f, "UYVY"),
+			PixelLayout::Nv12 => write!(f, "NV12"),
+			PixelLayout::Mjpeg => write!(f, "MJPEG"),
+			PixelLayout::Rgb => write!(f, "RGB"),
+			PixelLayout::Gray => write!(f, "GRAY"),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum ConvertError {
+	/// The frame isn't a size that makes sense for any layout we know.
+	UnknownLayout {
+		len: usize,
+	},
+	/// The frame's shorter than its layout says it should be.
+	Truncated {
+		len: usize,
+		expected: usize,
+	},
+	Mjpeg(nokhwa::NokhwaError),
+}
+
+impl fmt::Display for ConvertError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ConvertError::UnknownLayout { len } => {
+				write!(f, "can't tell what layout a {len} byte frame is")
+			}
+			ConvertError::Truncated { len, expected } => {
+				write!(f, "frame is {len} bytes but should be {expected}")
+			}
+			ConvertError::Mjpeg(e) => write!(f, "failed to decode MJPEG: {e}"),
+		}
+	}
+}
+
+/// A short frame would leave half of the last one behind, or panic.
+fn check_len(data: &[u8], expected: usize) -> Result<(), ConvertError> {
+	match data.len() < expected {
+		true => Err(ConvertError::Truncated {
+			len: data.len(),
+			expected,
+		}),
+		false => Ok(()),
+	}
+}
+
+/// Turns raw frames from the camera into 24bit RGB.
+///
+/// We start off trusting the [FrameFormat] the camera claims, but cameras lie
+/// (mine says one thing and sends UYVY), so the first frames are checked
+/// against what they look like and we go with that if they disagree.
+pub struct Converter {
+	width: usize,
+	height: usize,
+	claimed: PixelLayout,
+	/// UVC says uncompressed YUV from webcams is BT.601 limited range
+	space: ColorSpace,
+	/// What we've decided the frames really are, once we're sure.
+	detected: Option<PixelLayout>,
+	/// Packed 4:2:2 votes for UYVY minus votes for YUYV
+	byte_order_votes: i32,
+}
+
+/// How many frames have to agree on 4:2:2 byte order before we stop looking
+const BYTE_ORDER_VOTES: i32 = 5;
+
+impl Converter {
+	pub fn new(format: FrameFormat, width: usize, height: usize) -> Self {
+		Self {
+			width,
+			height,
+			claimed: PixelLayout::from_frame_format(format),
+			space: ColorSpace::BT601_LIMITED,
+			detected: None,
+			byte_order_votes: 0,
+		}
+	}
+
+	/// Convert `data` into `rgb`, which has to be width * height * 3 long.
+	pub fn convert(&mut self, data: &[u8], rgb: &mut [u8]) -> Result<(), ConvertError> {
+		let layout = match self.detected {
+			Some(layout) => layout,
+			None => self.detect(data)?,
+		};
+
+		let (space, width, height) = (self.space, self.width, self.height);
+		// Cameras drop bytes sometimes, and streams can end partway through
+		if let Some(expected) = layout.frame_len(width, height) {
+			check_len(data, expected)?;
+		}
+
+		match layout {
+			PixelLayout::Yuyv => colorconv::yuyv_to_rgb(space, data, rgb, width, height),
+			PixelLayout::Uyvy => colorconv::uyvy_to_rgb(space, data, rgb, width, height),
+			PixelLayout::Nv12 => colorconv::nv12_to_rgb(space, data, rgb, width, height),
+			PixelLayout::Gray => {
+				for (g, px) in data.iter().zip(rgb.chunks_exact_mut(3)) {
+					px.fill(*g);
+				}
+			}
+			PixelLayout::Rgb => {
+				let len = rgb.len().min(data.len());
+				rgb[..len].copy_from_slice(&data[..len]);
+			}
+			PixelLayout::Mjpeg => {
+				let resolution = Resolution::new(width as u32, height as u32);
+				Buffer::new(resolution, data, FrameFormat::MJPEG)
+					.decode_image_to_buffer::<RgbFormat>(rgb)
+					.map_err(ConvertError::Mjpeg)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Work out what the frame actually is. Returns the layout to use for
+	/// this frame and sets [Converter::detected] once we're sure.
+	fn detect(&mut self, data: &[u8]) -> Result<PixelLayout, ConvertError> {
+		let (width, height) = (self.width, self.height);
+
+		// JPEGs start with the Start Of Image marker
+		let guess = if data.starts_with(&[0xFF, 0xD8]) {
+			PixelLayout::Mjpeg
+		} else if self.claimed.frame_len(width, height) == Some(data.len()) {
+			self.claimed
+		} else {
+			// The camera lied, go by the size instead
+			[
+				PixelLayout::Rgb,
+				PixelLayout::Yuyv,
+				PixelLayout::Nv12,
+				PixelLayout::Gray,
+			]
+			.into_iter()
+			.find(|l| l.frame_len(width, height) == Some(data.len()))
+			.ok_or(ConvertError::UnknownLayout { len: data.len() })?
+		};
+
+		match guess {
+			PixelLayout::Yuyv | PixelLayout::Uyvy => {
+				// Both have the same size so we have to look at the bytes
+				self.byte_order_votes += match chroma_first(data) {
+					Some(true) => 1,
+					Some(false) => -1,
+					None => 0,
+				};
+
+				let layout = if self.byte_order_votes > 0 {
+					PixelLayout::Uyvy
+				} else if self.byte_order_votes < 0 {
+					PixelLayout::Yuyv
+				} else {
+					guess
+				};
+
+				if self.byte_order_votes.abs() >= BYTE_ORDER_VOTES {
+					self.settle(layout);
+				}
+				Ok(layout)
+			}
+			layout => {
+				self.settle(layout);
+				Ok(layout)
+			}
+		}
+	}
+
+	fn settle(&mut self, layout: PixelLayout) {
+		if layout != self.claimed {
+			println!(
+				"Camera says it's sending {} but it looks like {layout}, using that",
+				self.claimed
+			);
+		}
+		self.detected = Some(layout);
+	}
+}
+
+/// Guess if packed 4:2:2 has chroma in the even bytes (UYVY) or the odd ones
+/// (YUYV). None if we can't tell, like if the frame is flat grey.
+///
+/// Chroma tends to sit near 128 and change slowly, luma moves around a lot
+/// more. So whichever set of bytes is further from 128 and changes more from
+/// one pair of pixels to the next is probably the luma.
+fn chroma_first(data: &[u8]) -> Option<bool> {
+	let mut score = [0u64; 2];
+
+	// We don't need to look at every byte to get a good idea
+	for chunk in data.chunks_exact(8).step_by(7) {
+		for (phase, score) in score.iter_mut().enumerate() {
+			let a = chunk[phase] as i32;
+			let b = chunk[phase + 4] as i32;
+
+			*score += ((a - 128).abs() + (a - b).abs()) as u64;
+		}
+	}
+
+	let (even, odd) = (score[0], score[1]);
+	let margin = even.max(odd) / 8;
+	if even + margin < odd {
+		// even bytes are quieter, so they're chroma
+		Some(true)
+	} else if odd + margin < even {
+		Some(false)
+	} else {
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const WIDTH: usize = 33;
+	const HEIGHT: usize = 17;
+
+	/// Mostly grey with the brightness all over the place, which is what the
+	/// byte order guess looks for, and a bit of colour so it's not too easy.
+	fn picture() -> Vec<u8> {
+		let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+		for (idx, px) in rgb.chunks_exact_mut(3).enumerate() {
+			let v = ((idx * 2654435761) >> 7) as u8;
+			px.copy_from_slice(&[v, v.saturating_add(10), v.saturating_sub(10)]);
+		}
+		rgb
+	}
+
+	fn converted(converter: &mut Converter, data: &[u8]) -> Result<Vec<u8>, ConvertError> {
+		let mut rgb = vec![0; WIDTH * HEIGHT * 3];
+		converter.convert(data, &mut rgb).map(|_| rgb)
+	}
+
+	#[test]
+	fn frame_len_matches_colorconv() {
+		assert_eq!(PixelLayout::Yuyv.frame_len(5, 3), Some(3 * 4 * 3));
+		assert_eq!(PixelLayout::Uyvy.frame_len(4, 3), Some(2 * 4 * 3));
+		assert_eq!(PixelLayout::Nv12.frame_len(5, 3), Some(15 + 3 * 2 * 2));
+		assert_eq!(PixelLayout::Mjpeg.frame_len(5, 3), None);
+	}
+
+	#[test]
+	fn claims_yuyv_sends_uyvy() {
+		let rgb = picture();
+		let mut uyvy = vec![0; colorconv::packed422_len(WIDTH, HEIGHT)];
+		colorconv::rgb_to_uyvy(ColorSpace::BT601_LIMITED, &rgb, &mut uyvy, WIDTH, HEIGHT);
+
+		let mut converter = Converter::new(FrameFormat::YUYV, WIDTH, HEIGHT);
+		for _ in 0..BYTE_ORDER_VOTES {
+			converted(&mut converter, &uyvy).unwrap();
+		}
+		assert_eq!(converter.detected, Some(PixelLayout::Uyvy));
+
+		// And it actually comes out as the picture, give or take the chroma
+		let out = converted(&mut converter, &uyvy).unwrap();
+		assert!(rgb.iter().zip(&out).all(|(a, b)| a.abs_diff(*b) <= 16));
+	}
+
+	#[test]
+	fn claims_yuyv_sends_yuyv() {
+		let mut yuyv = vec![0; colorconv::packed422_len(WIDTH, HEIGHT)];
+		colorconv::rgb_to_yuyv(
+			ColorSpace::BT601_LIMITED,
+			&picture(),
+			&mut yuyv,
+			WIDTH,
+			HEIGHT,
+		);
+
+		let mut converter = Converter::new(FrameFormat::YUYV, WIDTH, HEIGHT);
+		for _ in 0..BYTE_ORDER_VOTES {
+			converted(&mut converter, &yuyv).unwrap();
+		}
+		assert_eq!(converter.detected, Some(PixelLayout::Yuyv));
+	}
+
+	#[test]
+	fn jpeg_marker_means_mjpeg() {
+		let mut data = vec![0; colorconv::packed422_len(WIDTH, HEIGHT)];
+		data[..2].copy_from_slice(&[0xFF, 0xD8]);
+
+		let mut converter = Converter::new(FrameFormat::YUYV, WIDTH, HEIGHT);
+		assert_eq!(converter.detect(&data).unwrap(), PixelLayout::Mjpeg);
+		assert_eq!(converter.detected, Some(PixelLayout::Mjpeg));
+	}
+
+	#[test]
+	fn goes_by_size_when_the_claim_is_wrong() {
+		let rgb = picture();
+
+		let mut converter = Converter::new(FrameFormat::NV12, WIDTH, HEIGHT);
+		assert_eq!(converted(&mut converter, &rgb).unwrap(), rgb);
+		assert_eq!(converter.detected, Some(PixelLayout::Rgb));
+	}
+
+	#[test]
+	fn short_frames_are_errors() {
+		let len = colorconv::yuv420_len(WIDTH, HEIGHT);
+		let mut converter = Converter::new(FrameFormat::NV12, WIDTH, HEIGHT);
+
+		// Before we know what it is, it's no size we know
+		let result = converted(&mut converter, &vec![128; len - 1]);
+		assert!(matches!(result, Err(ConvertError::UnknownLayout { .. })));
+
+		// And after
+		converted(&mut converter, &vec![128; len]).unwrap();
+		let result = converted(&mut converter, &vec![128; len - 1]);
+		assert!(matches!(
+			result,
+			Err(ConvertError::Truncated { expected, .. }) if expected == len
+		));
+	}
+}
@@ -0,0 +1,123 @@
+use nokhwa::{
+	pixel_format::RgbFormat,
+	utils::{
+		ApiBackend, CameraControl, CameraFormat, CameraIndex, CameraInfo, ControlValueSetter,
+		KnownCameraControl, RequestedFormat, RequestedFormatType,
+	},
+	Camera,
+};
+
+use super::{FrameSource, SourceError};
+use crate::pixfmt::Converter;
+
+/// All the cameras we can see right now.
+pub fn cameras() -> Vec<CameraInfo> {
+	match nokhwa::query(ApiBackend::Auto) {
+		Ok(cameras) => cameras,
+		Err(e) => {
+			eprintln!("failed to query cameras: {e}");
+			vec![]
+		}
+	}
+}
+
+/// Open the camera just long enough to ask it what formats it can do. This
+/// fails if the camera is already open, so it's for when we're not capturing.
+pub fn camera_formats(index: &CameraIndex) -> Vec<CameraFormat> {
+	let requested = RequestedFormat::new::<RgbFormat>(RequestedFormatType::None);
+
+	match Camera::new(index.clone(), requested).and_then(|mut c| c.compatible_camera_formats()) {
+		Ok(formats) => sorted_formats(formats),
+		Err(e) => {
+			eprintln!("failed to get formats for camera {index}: {e}");
+			vec![]
+		}
+	}
+}
+
+/// Biggest first, then fastest first.
+pub(super) fn sorted_formats(mut formats: Vec<CameraFormat>) -> Vec<CameraFormat> {
+	formats.sort_by_key(|f| {
+		(
+			std::cmp::Reverse(f.width() * f.height()),
+			std::cmp::Reverse(f.frame_rate()),
+			f.format().to_string(),
+		)
+	});
+	formats
+}
+
+/// A webcam, through nokhwa.
+pub struct CameraSource {
+	camera: Camera,
+	converter: Converter,
+}
+
+impl CameraSource {
+	pub fn open(index: CameraIndex, format: Option<CameraFormat>) -> Result<Self, SourceError> {
+		let requested_type = match format {
+			Some(format) => RequestedFormatType::Exact(format),
+			None => RequestedFormatType::AbsoluteHighestResolution,
+		};
+		let requested_format = RequestedFormat::new::<RgbFormat>(requested_type);
+		let mut camera = Camera::new(index, requested_format)?;
+
+		let format = camera.camera_format();
+		let converter = Converter::new(
+			format.format(),
+			format.width() as usize,
+			format.height() as usize,
+		);
+
+		println!("Opening stream...");
+		camera.open_stream()?;
+		println!("Opened!");
+
+		Ok(Self { camera, converter })
+	}
+}
+
+impl FrameSource for CameraSource {
+	fn name(&self) -> String {
+		self.camera.info().human_name()
+	}
+
+	fn format(&self) -> CameraFormat {
+		self.camera.camera_format()
+	}
+
+	fn formats(&mut self) -> Vec<CameraFormat> {
+		match self.camera.compatible_camera_formats() {
+			Ok(list) => sorted_formats(list),
+			Err(e) => {
+				eprintln!("failed to get camera formats: {e}");
+				vec![]
+			}
+		}
+	}
+
+	fn frame(&mut self, rgb: &mut [u8]) -> Result<(), SourceError> {
+		let raw = self.camera.frame_raw()?;
+		self.converter.convert(&raw, rgb)?;
+		Ok(())
+	}
+
+	fn controls(&self) -> Vec<CameraControl> {
+		match self.camera.camera_controls() {
+			Ok(controls) => controls,
+			Err(e) => {
+				eprintln!("failed to get camera controls: {e}");
+				vec![]
+			}
+		}
+	}
+
+	fn set_control(
+		&mut self,
+		control: KnownCameraControl,
+		value: ControlValueSetter,
+	) -> Result<(), SourceError> {
+		self.camera.set_camera_control(control, value)?;
+		Ok(())
+	}
+}
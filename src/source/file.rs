@@ -0,0 +1,245 @@
+use std::{
+	cmp::Ordering,
+	fs::{self, File},
+	io::BufReader,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use devout::Reader;
+use image::{imageops::FilterType, RgbImage};
+use nokhwa::utils::{CameraFormat, FrameFormat, Resolution};
+
+use super::{stream::StreamSource, FrameSource, Pacer, Pacing, SourceError};
+
+/// Open whatever's at `path`. Directories are image sequences, otherwise we
+/// go by the extension.
+///
+/// `format` is only used for the framerate of image sequences, everything
+/// else says how big and fast it is.
+pub fn open(
+	path: &Path,
+	format: Option<CameraFormat>,
+	pacing: Pacing,
+) -> Result<Box<dyn FrameSource>, SourceError> {
+	if path.is_dir() {
+		let fps = format.map(|f| f.frame_rate()).unwrap_or(30);
+		return Ok(Box::new(ImageSequence::open(path, fps, pacing)?));
+	}
+
+	let extension = path
+		.extension()
+		.map(|e| e.to_string_lossy().to_ascii_lowercase());
+
+	match extension.as_deref() {
+		Some("mp4" | "m4v" | "mov") => Ok(Box::new(Mp4Source::open(path, pacing)?)),
+		Some("y4m") => Ok(Box::new(StreamSource::new(
+			name(path),
+			Box::new(BufReader::new(File::open(path)?)),
+			format,
+			pacing,
+		)?)),
+		_ => Err(SourceError::Unsupported(format!(
+			"don't know how to read {}, it should be an MP4, a Y4M, or a directory of images",
+			path.display()
+		))),
+	}
+}
+
+fn name(path: &Path) -> String {
+	path.file_name()
+		.map(|n| n.to_string_lossy().into_owned())
+		.unwrap_or_else(|| path.display().to_string())
+}
+
+/// The H264 track of an MP4, decoded with [devout::Reader].
+pub struct Mp4Source {
+	name: String,
+	reader: Reader<BufReader<File>>,
+	format: CameraFormat,
+	pacer: Pacer,
+	decoded: Vec<u8>,
+	/// When the last frame we decoded is meant to be shown
+	timestamp: Option<Duration>,
+}
+
+impl Mp4Source {
+	pub fn open(path: &Path, pacing: Pacing) -> Result<Self, SourceError> {
+		let reader = Reader::file(path)?;
+		let fps = reader.framerate();
+		let format = CameraFormat::new(
+			Resolution::new(reader.width(), reader.height()),
+			FrameFormat::RAWRGB,
+			fps.round() as u32,
+		);
+
+		Ok(Self {
+			name: name(path),
+			reader,
+			format,
+			pacer: Pacer::new(pacing, fps),
+			decoded: vec![],
+			timestamp: None,
+		})
+	}
+}
+
+impl FrameSource for Mp4Source {
+	fn name(&self) -> String {
+		self.name.clone()
+	}
+
+	fn format(&self) -> CameraFormat {
+		self.format
+	}
+
+	fn formats(&mut self) -> Vec<CameraFormat> {
+		vec![self.format]
+	}
+
+	fn frame(&mut self, rgb: &mut [u8]) -> Result<(), SourceError> {
+		let frame = self
+			.reader
+			.next_frame(&mut self.decoded)?
+			.ok_or(SourceError::Finished)?;
+
+		// We go by the size in the header, we can't change halfway through
+		if self.decoded.len() != rgb.len() {
+			return Err(SourceError::Unsupported(format!(
+				"frame {} is {}x{} but the video is {}x{}",
+				frame.sample,
+				frame.width,
+				frame.height,
+				self.format.width(),
+				self.format.height()
+			)));
+		}
+
+		self.pacer.wait(frame.timestamp);
+		self.timestamp = Some(frame.timestamp);
+		rgb.copy_from_slice(&self.decoded);
+		Ok(())
+	}
+
+	fn timestamp(&self) -> Option<Duration> {
+		self.timestamp
+	}
+}
+
+/// A directory of PNGs or JPEGs, in the order of the numbers in their names.
+/// Everything is scaled to the size of the first one.
+pub struct ImageSequence {
+	name: String,
+	paths: Vec<PathBuf>,
+	next: usize,
+	format: CameraFormat,
+	pacer: Pacer,
+	frame_time: Duration,
+}
+
+impl ImageSequence {
+	pub fn open(dir: &Path, fps: u32, pacing: Pacing) -> Result<Self, SourceError> {
+		let mut paths = vec![];
+		for entry in fs::read_dir(dir)? {
+			let path = entry?.path();
+			let extension = path
+				.extension()
+				.map(|e| e.to_string_lossy().to_ascii_lowercase());
+
+			if let Some("png" | "jpg" | "jpeg") = extension.as_deref() {
+				paths.push(path);
+			}
+		}
+		paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+
+		let first = paths.first().ok_or_else(|| {
+			SourceError::Unsupported(format!("there's no PNGs or JPEGs in {}", dir.display()))
+		})?;
+		let (width, height) = image::image_dimensions(first)?;
+		let fps = fps.max(1);
+
+		Ok(Self {
+			name: name(dir),
+			paths,
+			next: 0,
+			format: CameraFormat::new(Resolution::new(width, height), FrameFormat::RAWRGB, fps),
+			pacer: Pacer::new(pacing, fps as f64),
+			frame_time: Duration::from_secs(1) / fps,
+		})
+	}
+}
+
+impl FrameSource for ImageSequence {
+	fn name(&self) -> String {
+		self.name.clone()
+	}
+
+	fn format(&self) -> CameraFormat {
+		self.format
+	}
+
+	fn formats(&mut self) -> Vec<CameraFormat> {
+		vec![self.format]
+	}
+
+	fn frame(&mut self, rgb: &mut [u8]) -> Result<(), SourceError> {
+		let idx = self.next;
+		let path = self.paths.get(idx).ok_or(SourceError::Finished)?;
+		// Move on even if this one's broken so we don't get stuck on it
+		self.next += 1;
+
+		let mut img: RgbImage = image::open(path)?.to_rgb8();
+		let (width, height) = (self.format.width(), self.format.height());
+		if img.dimensions() != (width, height) {
+			img = image::imageops::resize(&img, width, height, FilterType::Nearest);
+		}
+
+		self.pacer.wait(self.frame_time * idx as u32);
+		rgb.copy_from_slice(img.as_raw());
+		Ok(())
+	}
+
+	fn timestamp(&self) -> Option<Duration> {
+		let shown = self.next.checked_sub(1)?;
+		Some(self.frame_time * shown as u32)
+	}
+}
+
+/// Compare strings with runs of digits compared as numbers, so `frame2.png`
+/// comes before `frame10.png`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+	let (mut a, mut b) = (a, b);
+
+	loop {
+		let (Some(ca), Some(cb)) = (a.chars().next(), b.chars().next()) else {
+			return a.len().cmp(&b.len());
+		};
+
+		if ca.is_ascii_digit() && cb.is_ascii_digit() {
+			let (da, ra) = split_digits(a);
+			let (db, rb) = split_digits(b);
+
+			// Without the leading zeros, a longer number is a bigger one
+			let (za, zb) = (da.trim_start_matches('0'), db.trim_start_matches('0'));
+			let ord = za.len().cmp(&zb.len()).then_with(|| za.cmp(zb));
+			if ord != Ordering::Equal {
+				return ord;
+			}
+
+			a = ra;
+			b = rb;
+		} else {
+			if ca != cb {
+				return ca.cmp(&cb);
+			}
+
+			a = &a[ca.len_utf8()..];
+			b = &b[cb.len_utf8()..];
+		}
+	}
+}
+
+fn split_digits(s: &str) -> (&str, &str) {
+	let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+	s.split_at(end)
+}
@@ -0,0 +1,239 @@
+//! Where frames come from. Usually a webcam, but there's also a built in test
+//! pattern so we can work on things without one plugged in, and video files,
+//! folders of images, and Y4M or raw video on stdin for when you want to put
+//! effects on something you already have.
+
+use core::fmt;
+use std::{
+	io,
+	path::PathBuf,
+	thread,
+	time::{Duration, Instant},
+};
+
+use devout::DevoutError;
+use nokhwa::utils::{
+	CameraControl, CameraFormat, CameraIndex, ControlValueSetter, KnownCameraControl,
+};
+
+use crate::pixfmt::ConvertError;
+
+mod camera;
+mod file;
+mod pattern;
+mod stream;
+
+pub use camera::{camera_formats, cameras};
+
+/// Something that gives us frames of 24bit RGB.
+///
+/// Nokhwa's [nokhwa::Camera] can't be moved between threads, so sources are
+/// made with [open()] on the thread that's going to use them.
+pub trait FrameSource {
+	/// A name for people to read
+	fn name(&self) -> String;
+
+	/// The format frames are coming in as. The width and height are the size of
+	/// what [FrameSource::frame()] writes.
+	fn format(&self) -> CameraFormat;
+
+	/// Every format this source could be opened with, biggest first.
+	fn formats(&mut self) -> Vec<CameraFormat>;
+
+	/// Wait for the next frame and write it to `rgb`, which has to be
+	/// width * height * 3 long.
+	fn frame(&mut self, rgb: &mut [u8]) -> Result<(), SourceError>;
+
+	/// When the last frame from [FrameSource::frame()] is meant to be shown,
+	/// counting from the start of the source. Cameras don't know, so the
+	/// default is None and we go by when we got the frame.
+	fn timestamp(&self) -> Option<Duration> {
+		None
+	}
+
+	/// Settings like exposure and white balance. Only cameras have them.
+	fn controls(&self) -> Vec<CameraControl> {
+		vec![]
+	}
+
+	fn set_control(
+		&mut self,
+		control: KnownCameraControl,
+		_value: ControlValueSetter,
+	) -> Result<(), SourceError> {
+		Err(SourceError::Unsupported(format!(
+			"{} doesn't have a {control} control",
+			self.name()
+		)))
+	}
+}
+
+/// Which source to open.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SourceId {
+	Camera(CameraIndex),
+	TestPattern,
+	/// An MP4, a Y4M, or a directory of numbered PNGs or JPEGs
+	File(PathBuf),
+	/// Y4M, or raw frames in the format from `--format`
+	Stdin,
+}
+
+impl Default for SourceId {
+	fn default() -> Self {
+		SourceId::Camera(CameraIndex::Index(0))
+	}
+}
+
+impl fmt::Display for SourceId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			SourceId::Camera(index) => write!(f, "camera {index}"),
+			SourceId::TestPattern => write!(f, "test pattern"),
+			SourceId::File(path) => write!(f, "{}", path.display()),
+			SourceId::Stdin => write!(f, "stdin"),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+	Camera(nokhwa::NokhwaError),
+	/// We got a frame but couldn't make sense of it
+	Convert(ConvertError),
+	Io(io::Error),
+	Video(DevoutError),
+	Image(image::ImageError),
+	/// The input isn't something we can read
+	Unsupported(String),
+	/// There's no more frames, we got to the end of the file or stream.
+	Finished,
+	/// The camera's been unplugged and hasn't come back yet
+	Disconnected,
+}
+
+impl fmt::Display for SourceError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			SourceError::Camera(e) => write!(f, "{e}"),
+			SourceError::Convert(e) => write!(f, "{e}"),
+			SourceError::Io(e) => write!(f, "{e}"),
+			SourceError::Video(e) => write!(f, "{e}"),
+			SourceError::Image(e) => write!(f, "{e}"),
+			SourceError::Unsupported(msg) => write!(f, "{msg}"),
+			SourceError::Finished => write!(f, "no more frames"),
+			SourceError::Disconnected => write!(f, "the camera was disconnected"),
+		}
+	}
+}
+
+impl From<nokhwa::NokhwaError> for SourceError {
+	fn from(e: nokhwa::NokhwaError) -> Self {
+		SourceError::Camera(e)
+	}
+}
+
+impl From<ConvertError> for SourceError {
+	fn from(e: ConvertError) -> Self {
+		SourceError::Convert(e)
+	}
+}
+
+impl From<io::Error> for SourceError {
+	fn from(e: io::Error) -> Self {
+		SourceError::Io(e)
+	}
+}
+
+impl From<DevoutError> for SourceError {
+	fn from(e: DevoutError) -> Self {
+		SourceError::Video(e)
+	}
+}
+
+impl From<image::ImageError> for SourceError {
+	fn from(e: image::ImageError) -> Self {
+		SourceError::Image(e)
+	}
+}
+
+/// How fast sources that aren't cameras give us frames.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Pacing {
+	/// At the speed they're meant to be watched
+	#[default]
+	Realtime,
+	/// As fast as we can take them
+	Fast,
+}
+
+/// Waits so frames come out when they're meant to be shown.
+pub struct Pacer {
+	pacing: Pacing,
+	frame_time: Duration,
+	/// A time and the presentation time of the frame we showed then
+	anchor: Option<(Instant, Duration)>,
+}
+
+impl Pacer {
+	pub fn new(pacing: Pacing, fps: f64) -> Self {
+		Self {
+			pacing,
+			frame_time: Duration::from_secs_f64(1.0 / fps.max(1.0)),
+			anchor: None,
+		}
+	}
+
+	/// Wait for the frame that's shown `pts` after the start. If we've fallen
+	/// more than a frame behind we start counting from now rather than
+	/// rushing to catch up.
+	pub fn wait(&mut self, pts: Duration) {
+		if self.pacing == Pacing::Fast {
+			return;
+		}
+
+		let now = Instant::now();
+		let (then, then_pts) = *self.anchor.get_or_insert((now, pts));
+		let target = then + pts.saturating_sub(then_pts);
+
+		if pts < then_pts || now > target + self.frame_time {
+			self.anchor = Some((now, pts));
+		} else if target > now {
+			thread::sleep(target - now);
+		}
+	}
+}
+
+/// Open a source and get it ready to give us frames. `format` is what to ask
+/// for, None is the highest resolution it has. Files and stdin mostly know
+/// their own format, see [file::open()] and [stream::StreamSource].
+pub fn open(
+	id: &SourceId,
+	format: Option<CameraFormat>,
+	pacing: Pacing,
+) -> Result<Box<dyn FrameSource>, SourceError> {
+	Ok(match id {
+		SourceId::Camera(index) => Box::new(camera::CameraSource::open(index.clone(), format)?),
+		SourceId::TestPattern => Box::new(pattern::TestPattern::new(
+			format.unwrap_or_else(pattern::default_format),
+			pacing,
+		)),
+		SourceId::File(path) => file::open(path, format, pacing)?,
+		SourceId::Stdin => Box::new(stream::StreamSource::new(
+			String::from("stdin"),
+			Box::new(io::BufReader::new(io::stdin())),
+			format,
+			pacing,
+		)?),
+	})
+}
+
+/// The formats a source can do, without keeping it open.
+pub fn formats(id: &SourceId) -> Vec<CameraFormat> {
+	match id {
+		SourceId::Camera(index) => camera_formats(index),
+		SourceId::TestPattern => pattern::formats(),
+		// These are whatever they are
+		SourceId::File(_) | SourceId::Stdin => vec![],
+	}
+}
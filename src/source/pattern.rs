@@ -0,0 +1,269 @@
+use std::time::Duration;
+
+use nokhwa::utils::{CameraFormat, FrameFormat, Resolution};
+
+use super::{FrameSource, Pacer, Pacing, SourceError};
+
+/// What you get if you don't ask for anything else
+pub fn default_format() -> CameraFormat {
+	CameraFormat::new(Resolution::new(1280, 720), FrameFormat::RAWRGB, 30)
+}
+
+/// Some common sizes. The pattern can be any size at all, these are just
+/// what shows up in the format list.
+pub fn formats() -> Vec<CameraFormat> {
+	let mut formats = vec![];
+	for (width, height) in [(1920, 1080), (1280, 720), (640, 480)] {
+		for fps in [60, 30] {
+			formats.push(CameraFormat::new(
+				Resolution::new(width, height),
+				FrameFormat::RAWRGB,
+				fps,
+			));
+		}
+	}
+	formats
+}
+
+/// SMPTE colour bars with a box bouncing around, a clock, and some noise so
+/// there's always something moving. Frames come out at the format's framerate.
+pub struct TestPattern {
+	format: CameraFormat,
+	width: usize,
+	height: usize,
+	/// The bars, drawn once
+	background: Vec<u8>,
+
+	frame: u64,
+	pacer: Pacer,
+	frame_time: Duration,
+	noise: u64,
+}
+
+impl TestPattern {
+	pub fn new(format: CameraFormat, pacing: Pacing) -> Self {
+		let (width, height) = (format.width() as usize, format.height() as usize);
+		let fps = format.frame_rate().max(1);
+
+		let mut background = vec![0; width * height * 3];
+		smpte_bars(&mut background, width, height);
+
+		Self {
+			format,
+			width,
+			height,
+			background,
+			frame: 0,
+			pacer: Pacer::new(pacing, fps as f64),
+			frame_time: Duration::from_secs(1) / fps,
+			noise: 0x2545F4914F6CDD1D,
+		}
+	}
+
+	fn bouncing_box(&self, rgb: &mut [u8]) {
+		let size = (self.height / 8).max(1);
+		let x = bounce(self.frame as usize * 7, self.width.saturating_sub(size));
+		let y = bounce(self.frame as usize * 5, self.height.saturating_sub(size));
+
+		fill_rect(rgb, self.width, x, y, size, size, [255, 255, 255]);
+	}
+
+	/// Time since we started, as HH:MM:SS.FF where FF is the frame within the
+	/// second, in a black box near the top.
+	fn clock(&self, rgb: &mut [u8]) {
+		let fps = self.format.frame_rate().max(1) as u64;
+		let secs = self.frame / fps;
+		let text = format!(
+			"{:02}:{:02}:{:02}.{:02}",
+			secs / 3600,
+			(secs / 60) % 60,
+			secs % 60,
+			self.frame % fps
+		);
+
+		let scale = (self.height / 90).max(1);
+		let text_width = text.len() * 4 * scale;
+		let x = self.width.saturating_sub(text_width) / 2;
+		let y = self.height / 12;
+
+		fill_rect(
+			rgb,
+			self.width,
+			x.saturating_sub(scale * 2),
+			y.saturating_sub(scale * 2),
+			text_width + scale * 3,
+			5 * scale + scale * 4,
+			[0, 0, 0],
+		);
+
+		for (idx, chr) in text.chars().enumerate() {
+			draw_glyph(rgb, self.width, x + idx * 4 * scale, y, scale, chr);
+		}
+	}
+
+	/// Grey static in the bottom right corner, where the PLUGE would go.
+	fn noise(&mut self, rgb: &mut [u8]) {
+		let x0 = self.width * 20 / 28;
+		let y0 = self.height * 3 / 4;
+
+		for y in y0..self.height {
+			for x in x0..self.width {
+				// xorshift64
+				self.noise ^= self.noise << 13;
+				self.noise ^= self.noise >> 7;
+				self.noise ^= self.noise << 17;
+
+				let idx = (y * self.width + x) * 3;
+				rgb[idx..idx + 3].fill((self.noise >> 56) as u8);
+			}
+		}
+	}
+}
+
+impl FrameSource for TestPattern {
+	fn name(&self) -> String {
+		String::from("Test pattern")
+	}
+
+	fn format(&self) -> CameraFormat {
+		self.format
+	}
+
+	fn formats(&mut self) -> Vec<CameraFormat> {
+		formats()
+	}
+
+	fn frame(&mut self, rgb: &mut [u8]) -> Result<(), SourceError> {
+		self.pacer.wait(self.frame_time * self.frame as u32);
+
+		rgb.copy_from_slice(&self.background);
+		self.noise(rgb);
+		self.bouncing_box(rgb);
+		self.clock(rgb);
+
+		self.frame += 1;
+		Ok(())
+	}
+
+	fn timestamp(&self) -> Option<Duration> {
+		let shown = self.frame.checked_sub(1)?;
+		Some(self.frame_time * shown as u32)
+	}
+}
+
+/// Goes from 0 up to `max` and back down again as `t` goes up.
+fn bounce(t: usize, max: usize) -> usize {
+	if max == 0 {
+		return 0;
+	}
+
+	let t = t % (max * 2);
+	if t > max {
+		max * 2 - t
+	} else {
+		t
+	}
+}
+
+fn fill_rect(
+	rgb: &mut [u8],
+	stride: usize,
+	x: usize,
+	y: usize,
+	w: usize,
+	h: usize,
+	colour: [u8; 3],
+) {
+	let height = rgb.len() / 3 / stride.max(1);
+
+	for row in y..(y + h).min(height) {
+		for col in x..(x + w).min(stride) {
+			let idx = (row * stride + col) * 3;
+			rgb[idx..idx + 3].copy_from_slice(&colour);
+		}
+	}
+}
+
+/// The SMPTE ECR 1-1978 bars, more or less. Seven bars at 75%, a strip of the
+/// reversed blue bars under them, and then -I, white, +Q, and black along
+/// the bottom.
+fn smpte_bars(rgb: &mut [u8], width: usize, height: usize) {
+	const BARS: [[u8; 3]; 7] = [
+		[191, 191, 191],
+		[191, 191, 0],
+		[0, 191, 191],
+		[0, 191, 0],
+		[191, 0, 191],
+		[191, 0, 0],
+		[0, 0, 191],
+	];
+	const CASTELLATIONS: [[u8; 3]; 7] = [
+		[0, 0, 191],
+		[19, 19, 19],
+		[191, 0, 191],
+		[19, 19, 19],
+		[0, 191, 191],
+		[19, 19, 19],
+		[191, 191, 191],
+	];
+	const BOTTOM: [[u8; 3]; 4] = [[0, 33, 76], [255, 255, 255], [50, 0, 106], [19, 19, 19]];
+
+	let bars_end = height * 2 / 3;
+	let castellations_end = height * 3 / 4;
+
+	for y in 0..height {
+		for x in 0..width {
+			let bar = x * 7 / width;
+
+			let colour = if y < bars_end {
+				BARS[bar]
+			} else if y < castellations_end {
+				CASTELLATIONS[bar]
+			} else {
+				// The bottom row is in 28ths, the first four parts are 5 wide
+				*BOTTOM.get(x * 28 / width / 5).unwrap_or(&[19, 19, 19])
+			};
+
+			let idx = (y * width + x) * 3;
+			rgb[idx..idx + 3].copy_from_slice(&colour);
+		}
+	}
+}
+
+/// A 3x5 font, just enough for the clock. Each row is three bits, the high
+/// bit is the left pixel.
+fn glyph(chr: char) -> [u8; 5] {
+	match chr {
+		'0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+		'1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+		'2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+		'3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+		'4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+		'5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+		'6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+		'7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+		'8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+		'9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+		':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+		'.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+		_ => [0; 5],
+	}
+}
+
+fn draw_glyph(rgb: &mut [u8], stride: usize, x: usize, y: usize, scale: usize, chr: char) {
+	for (row, bits) in glyph(chr).into_iter().enumerate() {
+		for col in 0..3 {
+			if bits & (0b100 >> col) != 0 {
+				fill_rect(
+					rgb,
+					stride,
+					x + col * scale,
+					y + row * scale,
+					scale,
+					scale,
+					[255, 255, 255],
+				);
+			}
+		}
+	}
+}
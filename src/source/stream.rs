@@ -0,0 +1,268 @@
+use std::{
+	io::{self, Cursor, ErrorKind, Read},
+	time::Duration,
+};
+
+use devout::colorconv::{self, ColorSpace};
+use nokhwa::utils::{CameraFormat, FrameFormat, Resolution};
+
+use super::{FrameSource, Pacer, Pacing, SourceError};
+use crate::pixfmt::{Converter, PixelLayout};
+
+/// Y4M, or raw frames one after the other. We can tell which by the
+/// `YUV4MPEG2` at the start of a Y4M. Raw frames need a `--format` to say how
+/// big they are and what they're in, with RGB if there's no FourCC.
+pub struct StreamSource {
+	name: String,
+	input: Box<dyn Read>,
+	kind: StreamKind,
+	format: CameraFormat,
+	/// One frame, not counting any Y4M frame header
+	buffer: Vec<u8>,
+
+	frame: u64,
+	frame_time: Duration,
+	pacer: Pacer,
+}
+
+enum StreamKind {
+	Y4m { chroma: Chroma, space: ColorSpace },
+	Raw(Converter),
+}
+
+/// The Y4M chroma layouts we can read
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Chroma {
+	/// 420jpeg, 420mpeg2, and 420paldv all come out the same for us
+	C420,
+	C444,
+	Mono,
+}
+
+const Y4M_MAGIC: &[u8] = b"YUV4MPEG2";
+
+impl StreamSource {
+	pub fn new(
+		name: String,
+		mut input: Box<dyn Read>,
+		format: Option<CameraFormat>,
+		pacing: Pacing,
+	) -> Result<Self, SourceError> {
+		// Have a look at the start to see what it is
+		let mut magic = Vec::with_capacity(Y4M_MAGIC.len());
+		(&mut input)
+			.take(Y4M_MAGIC.len() as u64)
+			.read_to_end(&mut magic)?;
+
+		if magic == Y4M_MAGIC {
+			let header = read_line(&mut input)?.ok_or(SourceError::Finished)?;
+			return Self::y4m(name, input, &header, pacing);
+		}
+
+		let format = format.ok_or_else(|| {
+			SourceError::Unsupported(format!(
+				"{name} isn't Y4M, so use --format to say what the raw frames are"
+			))
+		})?;
+		let (width, height) = (format.width() as usize, format.height() as usize);
+		let frame_len = PixelLayout::from_frame_format(format.format())
+			.frame_len(width, height)
+			.ok_or_else(|| {
+				SourceError::Unsupported(String::from("raw frames can't be MJPEG, try Y4M"))
+			})?;
+
+		// Put back what we looked at
+		let input = Box::new(Cursor::new(magic).chain(input));
+		let converter = Converter::new(format.format(), width, height);
+		Ok(Self::with_kind(
+			name,
+			input,
+			StreamKind::Raw(converter),
+			format,
+			frame_len,
+			format.frame_rate() as f64,
+			pacing,
+		))
+	}
+
+	/// `header` is everything after the `YUV4MPEG2`, like
+	/// ` W1280 H720 F30:1 Ip A1:1 C420jpeg`.
+	fn y4m(
+		name: String,
+		input: Box<dyn Read>,
+		header: &str,
+		pacing: Pacing,
+	) -> Result<Self, SourceError> {
+		let bad = |what: &str| SourceError::Unsupported(format!("bad Y4M header: {what}"));
+
+		let mut width = None;
+		let mut height = None;
+		let mut fps = 30.0;
+		let mut chroma = Chroma::C420;
+		// Y4M doesn't say, but limited BT.601 is what everything assumes
+		let mut space = ColorSpace::BT601_LIMITED;
+
+		for param in header.split_ascii_whitespace() {
+			let mut chars = param.chars();
+			let tag = chars.next();
+			let value = chars.as_str();
+
+			match tag {
+				Some('W') => width = Some(value.parse::<u32>().map_err(|_| bad(param))?),
+				Some('H') => height = Some(value.parse::<u32>().map_err(|_| bad(param))?),
+				Some('F') => {
+					let (num, den) = value.split_once(':').ok_or_else(|| bad(param))?;
+					let num: f64 = num.parse().map_err(|_| bad(param))?;
+					let den: f64 = den.parse().map_err(|_| bad(param))?;
+					if num > 0.0 && den > 0.0 {
+						fps = num / den;
+					}
+				}
+				Some('C') => {
+					chroma = match value {
+						"420" | "420jpeg" | "420mpeg2" | "420paldv" => Chroma::C420,
+						"444" => Chroma::C444,
+						"mono" => Chroma::Mono,
+						_ => {
+							return Err(SourceError::Unsupported(format!(
+								"can't read Y4M with {value} chroma, only 420, 444, and mono"
+							)))
+						}
+					}
+				}
+				Some('X') if value == "COLORRANGE=FULL" => space = ColorSpace::BT601_FULL,
+				// Interlacing, aspect ratio, and other extensions we don't care
+				// about
+				_ => (),
+			}
+		}
+
+		let (width, height) = match (width, height) {
+			(Some(w), Some(h)) => (w, h),
+			_ => return Err(bad("it needs a W and an H")),
+		};
+		let (w, h) = (width as usize, height as usize);
+		let frame_len = match chroma {
+			Chroma::C420 => colorconv::yuv420_len(w, h),
+			Chroma::C444 => w * h * 3,
+			Chroma::Mono => w * h,
+		};
+
+		let format = CameraFormat::new(
+			Resolution::new(width, height),
+			FrameFormat::RAWRGB,
+			fps.round() as u32,
+		);
+		Ok(Self::with_kind(
+			name,
+			input,
+			StreamKind::Y4m { chroma, space },
+			format,
+			frame_len,
+			fps,
+			pacing,
+		))
+	}
+
+	fn with_kind(
+		name: String,
+		input: Box<dyn Read>,
+		kind: StreamKind,
+		format: CameraFormat,
+		frame_len: usize,
+		fps: f64,
+		pacing: Pacing,
+	) -> Self {
+		Self {
+			name,
+			input,
+			kind,
+			format,
+			buffer: vec![0; frame_len],
+			frame: 0,
+			frame_time: Duration::from_secs_f64(1.0 / fps.max(1.0)),
+			pacer: Pacer::new(pacing, fps),
+		}
+	}
+}
+
+impl FrameSource for StreamSource {
+	fn name(&self) -> String {
+		self.name.clone()
+	}
+
+	fn format(&self) -> CameraFormat {
+		self.format
+	}
+
+	fn formats(&mut self) -> Vec<CameraFormat> {
+		vec![self.format]
+	}
+
+	fn frame(&mut self, rgb: &mut [u8]) -> Result<(), SourceError> {
+		if let StreamKind::Y4m { .. } = self.kind {
+			// Every frame starts with FRAME and maybe some parameters we ignore
+			match read_line(&mut self.input)? {
+				Some(line) if line.starts_with("FRAME") => (),
+				Some(line) => {
+					return Err(SourceError::Unsupported(format!(
+						"expected a Y4M FRAME but got '{line}'"
+					)))
+				}
+				None => return Err(SourceError::Finished),
+			}
+		}
+
+		match self.input.read_exact(&mut self.buffer) {
+			Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Err(SourceError::Finished),
+			result => result?,
+		}
+
+		let (width, height) = (self.format.width() as usize, self.format.height() as usize);
+		match &mut self.kind {
+			StreamKind::Raw(converter) => converter.convert(&self.buffer, rgb)?,
+			StreamKind::Y4m { chroma, space } => match chroma {
+				Chroma::C420 => colorconv::i420_to_rgb(*space, &self.buffer, rgb, width, height),
+				Chroma::C444 => {
+					let (y, uv) = self.buffer.split_at(width * height);
+					let (u, v) = uv.split_at(width * height);
+
+					for (idx, px) in rgb.chunks_exact_mut(3).enumerate() {
+						px.copy_from_slice(&colorconv::yuv_to_rgb(*space, y[idx], u[idx], v[idx]));
+					}
+				}
+				Chroma::Mono => {
+					for (y, px) in self.buffer.iter().zip(rgb.chunks_exact_mut(3)) {
+						px.copy_from_slice(&colorconv::yuv_to_rgb(*space, *y, 128, 128));
+					}
+				}
+			},
+		}
+
+		self.pacer.wait(self.frame_time * self.frame as u32);
+		self.frame += 1;
+		Ok(())
+	}
+
+	fn timestamp(&self) -> Option<Duration> {
+		let shown = self.frame.checked_sub(1)?;
+		Some(self.frame_time * shown as u32)
+	}
+}
+
+/// Read up to a newline. None if we're already at the end.
+fn read_line<R: Read + ?Sized>(input: &mut R) -> io::Result<Option<String>> {
+	let mut line = vec![];
+	let mut byte = [0];
+
+	loop {
+		match input.read(&mut byte)? {
+			0 if line.is_empty() => return Ok(None),
+			0 => break,
+			_ if byte[0] == b'\n' => break,
+			_ => line.push(byte[0]),
+		}
+	}
+
+	Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
@@ -0,0 +1,419 @@
+use devout::bands;
+
+use super::{Param, ParamKind, ParamValue, Vex};
+use crate::capture::{BorrowedFrame, Frame};
+
+/// We stop making the pyramid smaller once a level is about this big
+const SMALLEST_LEVEL: usize = 64;
+/// How many times the pyramid can halve
+const MAX_LEVELS: usize = 5;
+
+/// Holds the picture still when the camera's shaking, so trichrome only
+/// fringes what actually moved. Put it before trichrome.
+///
+/// Every frame is compared with the one before to see how far the whole
+/// picture moved, by block matching on smaller and smaller copies of them:
+/// the smallest is searched all over, then each bigger one only has to look a
+/// pixel either side of where the last one said. The frame is then shifted
+/// back by however far the camera's moved, with the edges stretched to fill
+/// the gap. `follow` lets that drift back to nothing so a slow pan still pans.
+pub struct Align {
+	buffer: Frame,
+	/// How far the picture can move between frames before we lose track
+	reach: usize,
+	/// How much of the camera's movement we let through each frame
+	follow: f32,
+	/// The last frame's pyramid, biggest first
+	previous: Vec<Luma>,
+	/// How far we're shifting the frame, in pixels
+	offset: (f32, f32),
+}
+
+impl Align {
+	pub const PARAMS: &'static [Param] = &[
+		Param {
+			name: "reach",
+			label: "Reach",
+			kind: ParamKind::Int {
+				min: 2,
+				max: 64,
+				default: 16,
+			},
+		},
+		Param {
+			name: "follow",
+			label: "Follow",
+			kind: ParamKind::Float {
+				min: 0.0,
+				max: 1.0,
+				default: 0.1,
+			},
+		},
+	];
+
+	pub fn from_frame(frame: Frame) -> Self {
+		Self {
+			buffer: frame,
+			reach: Self::PARAMS[0].default().as_int() as usize,
+			follow: Self::PARAMS[1].default().as_float(),
+			previous: vec![],
+			offset: (0.0, 0.0),
+		}
+	}
+}
+
+impl Vex for Align {
+	fn into_frame(self: Box<Self>) -> Frame {
+		self.buffer
+	}
+
+	fn frame_in(&mut self, frame: BorrowedFrame) {
+		let pyramid = pyramid(&frame);
+
+		// A new size, or the first frame, so there's nothing to compare with
+		let same_size = self.previous.first().map(|l| (l.width, l.height))
+			== pyramid.first().map(|l| (l.width, l.height));
+		if same_size {
+			let (dx, dy) = motion(&self.previous, &pyramid, self.reach);
+			let keep = 1.0 - self.follow;
+			self.offset = (
+				(self.offset.0 + dx as f32) * keep,
+				(self.offset.1 + dy as f32) * keep,
+			);
+		} else {
+			self.offset = (0.0, 0.0);
+		}
+		self.previous = pyramid;
+
+		shift(
+			&frame,
+			&mut self.buffer,
+			self.offset.0.round() as isize,
+			self.offset.1.round() as isize,
+		);
+	}
+
+	fn frame_out(&mut self) -> BorrowedFrame<'_> {
+		self.buffer.borrow()
+	}
+
+	fn params(&self) -> &'static [Param] {
+		Self::PARAMS
+	}
+
+	fn param(&self, name: &str) -> Option<ParamValue> {
+		match name {
+			"reach" => Some(ParamValue::Int(self.reach as i32)),
+			"follow" => Some(ParamValue::Float(self.follow)),
+			_ => None,
+		}
+	}
+
+	fn set_param(&mut self, name: &str, value: ParamValue) {
+		match name {
+			"reach" => self.reach = value.as_int().max(1) as usize,
+			"follow" => self.follow = value.as_float().clamp(0.0, 1.0),
+			_ => (),
+		}
+	}
+}
+
+/// Just the brightness, which is all we need to line things up
+struct Luma {
+	data: Vec<u8>,
+	width: usize,
+	height: usize,
+}
+
+impl Luma {
+	fn at(&self, x: usize, y: usize) -> u8 {
+		self.data[y * self.width + x]
+	}
+
+	/// Half the size, each pixel the average of the four under it
+	fn half(&self) -> Luma {
+		let (width, height) = (self.width / 2, self.height / 2);
+		let mut data = Vec::with_capacity(width * height);
+
+		for y in 0..height {
+			for x in 0..width {
+				let sum = self.at(x * 2, y * 2) as u32
+					+ self.at(x * 2 + 1, y * 2) as u32
+					+ self.at(x * 2, y * 2 + 1) as u32
+					+ self.at(x * 2 + 1, y * 2 + 1) as u32;
+				data.push((sum / 4) as u8);
+			}
+		}
+
+		Luma {
+			data,
+			width,
+			height,
+		}
+	}
+}
+
+/// The frame's brightness, then halved until it's small, biggest first.
+fn pyramid(frame: &BorrowedFrame) -> Vec<Luma> {
+	let mut data = vec![0; frame.width * frame.height];
+	bands::in_bands(&mut data, frame.width, 1, |first, rows| {
+		let input = frame
+			.data
+			.get(first * frame.width * 3..)
+			.unwrap_or_default();
+		for (luma, px) in rows.iter_mut().zip(input.chunks_exact(3)) {
+			*luma = ((px[0] as u32 + px[1] as u32 * 2 + px[2] as u32) / 4) as u8;
+		}
+	});
+
+	let mut levels = vec![Luma {
+		data,
+		width: frame.width,
+		height: frame.height,
+	}];
+
+	while levels.len() < MAX_LEVELS {
+		let last = &levels[levels.len() - 1];
+		if last.width / 2 < SMALLEST_LEVEL || last.height / 2 < SMALLEST_LEVEL {
+			break;
+		}
+		levels.push(last.half());
+	}
+
+	levels
+}
+
+/// How far the picture moved from `previous` to `current`, in pixels at full
+/// size, looking no further than `reach`.
+fn motion(previous: &[Luma], current: &[Luma], reach: usize) -> (isize, isize) {
+	let top = current.len() - 1;
+
+	// Everywhere within reach on the smallest level
+	let radius = (reach >> top).max(1) as isize;
+	let mut best = search(&previous[top], &current[top], (0, 0), radius, 1);
+
+	// Then a pixel either side of that on each bigger one. The biggest is
+	// the slowest, so we only look at every other row and column of it.
+	for level in (0..top).rev() {
+		let step = if level == 0 { 2 } else { 1 };
+		let guess = (best.0 * 2, best.1 * 2);
+		best = search(&previous[level], &current[level], guess, 1, step);
+	}
+
+	let reach = reach as isize;
+	(best.0.clamp(-reach, reach), best.1.clamp(-reach, reach))
+}
+
+/// The move within `radius` of `around` where `current` looks most like
+/// `previous`.
+fn search(
+	previous: &Luma,
+	current: &Luma,
+	around: (isize, isize),
+	radius: isize,
+	step: usize,
+) -> (isize, isize) {
+	let mut best = around;
+	let mut best_score = u64::MAX;
+
+	for dy in around.1 - radius..=around.1 + radius {
+		for dx in around.0 - radius..=around.0 + radius {
+			let Some(score) = difference(previous, current, dx, dy, step) else {
+				continue;
+			};
+
+			// Ties go to the smaller move, so a flat picture stays put
+			let closer = dx.abs() + dy.abs() < best.0.abs() + best.1.abs();
+			if score < best_score || (score == best_score && closer) {
+				best = (dx, dy);
+				best_score = score;
+			}
+		}
+	}
+
+	best
+}
+
+/// The average difference between `current` and `previous` moved by
+/// `(dx, dy)`, over where they overlap. None if they hardly overlap, it's not
+/// fair to compare a sliver with a whole picture.
+fn difference(previous: &Luma, current: &Luma, dx: isize, dy: isize, step: usize) -> Option<u64> {
+	let (width, height) = (current.width as isize, current.height as isize);
+	if dx.abs() * 2 >= width || dy.abs() * 2 >= height {
+		return None;
+	}
+
+	let xs = dx.max(0) as usize..(width + dx.min(0)) as usize;
+	let ys = dy.max(0) as usize..(height + dy.min(0)) as usize;
+
+	let mut sum = 0u64;
+	let mut count = 0u64;
+	for y in ys.step_by(step) {
+		let py = (y as isize - dy) as usize;
+		let cur = &current.data[y * current.width..][xs.clone()];
+		let start = (xs.start as isize - dx) as usize;
+		let prev = &previous.data[py * previous.width + start..][..xs.len()];
+
+		for (c, p) in cur.iter().step_by(step).zip(prev.iter().step_by(step)) {
+			sum += c.abs_diff(*p) as u64;
+			count += 1;
+		}
+	}
+
+	// Scaled up so small differences still count
+	(count > 0).then(|| sum * 256 / count)
+}
+
+/// Move `frame` back by `(dx, dy)` into `out`, so what's at `(dx, dy)` ends up
+/// at the corner. What's past the edge is the edge stretched out.
+fn shift(frame: &BorrowedFrame, out: &mut Frame, dx: isize, dy: isize) {
+	let (width, height) = (frame.width, frame.height);
+	out.data.resize(frame.data.len(), 0);
+	out.width = width;
+	out.height = height;
+
+	if dx == 0 && dy == 0 {
+		out.data.copy_from_slice(frame.data);
+		return;
+	}
+
+	let max_y = height as isize - 1;
+	// The columns that come from inside the frame, the rest are stretched
+	let inside_start = (-dx).clamp(0, width as isize) as usize;
+	let inside_end = (width as isize - dx).clamp(0, width as isize) as usize;
+	let inside = inside_start..inside_end.max(inside_start);
+	let source_start = (inside.start as isize + dx).clamp(0, width as isize) as usize;
+
+	bands::in_bands(&mut out.data, width * 3, 1, |first, rows| {
+		for (y, row) in rows.chunks_exact_mut(width * 3).enumerate() {
+			let sy = (first as isize + y as isize + dy).clamp(0, max_y) as usize;
+			let src = &frame.data[sy * width * 3..][..width * 3];
+
+			row[inside.start * 3..inside.end * 3]
+				.copy_from_slice(&src[source_start * 3..(source_start + inside.len()) * 3]);
+
+			let (left, right) = (&src[..3], &src[(width - 1) * 3..]);
+			for px in row[..inside.start * 3].chunks_exact_mut(3) {
+				px.copy_from_slice(left);
+			}
+			for px in row[inside.end * 3..].chunks_exact_mut(3) {
+				px.copy_from_slice(right);
+			}
+		}
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const WIDTH: usize = 256;
+	const HEIGHT: usize = 192;
+	/// How much bigger the scene is than the frames cut out of it
+	const MARGIN: usize = 32;
+
+	/// Smooth random blobs, grey, so there's one clear place where two
+	/// frames line up and the smaller levels of the pyramid still see it.
+	fn scene() -> Vec<u8> {
+		const CELL: usize = 8;
+		let (width, height) = (WIDTH + MARGIN * 2, HEIGHT + MARGIN * 2);
+		let corner = |cx: usize, cy: usize| {
+			(((cx * 7919) ^ (cy * 104729)).wrapping_mul(2654435761) >> 13) as u8 as f32
+		};
+
+		let mut luma = vec![0; width * height];
+		for y in 0..height {
+			for x in 0..width {
+				let (cx, cy) = (x / CELL, y / CELL);
+				let (fx, fy) = (
+					(x % CELL) as f32 / CELL as f32,
+					(y % CELL) as f32 / CELL as f32,
+				);
+				let top = corner(cx, cy) * (1.0 - fx) + corner(cx + 1, cy) * fx;
+				let bottom = corner(cx, cy + 1) * (1.0 - fx) + corner(cx + 1, cy + 1) * fx;
+				luma[y * width + x] = (top * (1.0 - fy) + bottom * fy) as u8;
+			}
+		}
+		luma
+	}
+
+	/// The scene as the camera would see it if it had moved so everything
+	/// is `(dx, dy)` further over than in the middle.
+	fn view(scene: &[u8], dx: isize, dy: isize) -> Vec<u8> {
+		let width = WIDTH + MARGIN * 2;
+		let (left, top) = (MARGIN as isize - dx, MARGIN as isize - dy);
+
+		let mut rgb = Vec::with_capacity(WIDTH * HEIGHT * 3);
+		for y in 0..HEIGHT {
+			for x in 0..WIDTH {
+				let idx = (top as usize + y) * width + left as usize + x;
+				rgb.extend_from_slice(&[scene[idx]; 3]);
+			}
+		}
+		rgb
+	}
+
+	fn borrow(data: &[u8]) -> BorrowedFrame<'_> {
+		BorrowedFrame {
+			data,
+			width: WIDTH,
+			height: HEIGHT,
+		}
+	}
+
+	#[test]
+	fn finds_how_far_it_moved() {
+		let scene = scene();
+		let still = pyramid(&borrow(&view(&scene, 0, 0)));
+
+		for moved in [(0, 0), (5, -3), (-12, 7), (16, 16), (-1, 0)] {
+			let current = pyramid(&borrow(&view(&scene, moved.0, moved.1)));
+			assert_eq!(motion(&still, &current, 16), moved);
+		}
+	}
+
+	#[test]
+	fn shift_puts_it_back() {
+		let scene = scene();
+		let still = view(&scene, 0, 0);
+		let (dx, dy) = (5, -3);
+		let moved = view(&scene, dx, dy);
+
+		let mut out = Frame {
+			data: vec![],
+			width: 0,
+			height: 0,
+		};
+		shift(&borrow(&moved), &mut out, dx, dy);
+		assert_eq!((out.width, out.height), (WIDTH, HEIGHT));
+
+		// Everywhere but the edges that had to be stretched in
+		let row_len = WIDTH * 3;
+		for y in 3..HEIGHT {
+			let row = y * row_len;
+			assert_eq!(
+				out.data[row..row + row_len - 5 * 3],
+				still[row..row + row_len - 5 * 3],
+				"row {y}"
+			);
+		}
+	}
+
+	#[test]
+	fn flat_stays_put() {
+		let flat = vec![128; WIDTH * HEIGHT * 3];
+		let levels = pyramid(&borrow(&flat));
+		assert_eq!(motion(&levels, &pyramid(&borrow(&flat)), 16), (0, 0));
+
+		let mut align = Align::from_frame(Frame {
+			data: vec![],
+			width: 0,
+			height: 0,
+		});
+		for _ in 0..3 {
+			align.frame_in(borrow(&flat));
+		}
+		assert_eq!(align.offset, (0.0, 0.0));
+		assert_eq!(align.frame_out().data, &flat[..]);
+	}
+}
@@ -0,0 +1,101 @@
+use devout::bands;
+
+use super::{Param, ParamKind, ParamValue, Vex};
+use crate::capture::{BorrowedFrame, Frame};
+
+/// Film grain, or at least some noise that looks a bit like it. The same
+/// amount is added to all three channels so it doesn't go rainbow.
+pub struct Grain {
+	buffer: Frame,
+	/// How far a pixel can be pushed either way
+	amount: u8,
+	/// Changes every frame so the grain moves
+	seed: u64,
+}
+
+impl Grain {
+	pub const PARAMS: &'static [Param] = &[Param {
+		name: "amount",
+		label: "Amount",
+		kind: ParamKind::Int {
+			min: 0,
+			max: 128,
+			default: 24,
+		},
+	}];
+
+	pub fn from_frame(frame: Frame) -> Self {
+		Self {
+			buffer: frame,
+			amount: Self::PARAMS[0].default().as_int() as u8,
+			seed: 0x2545F4914F6CDD1D,
+		}
+	}
+}
+
+impl Vex for Grain {
+	fn into_frame(self: Box<Self>) -> Frame {
+		self.buffer
+	}
+
+	fn frame_in(&mut self, frame: BorrowedFrame) {
+		self.buffer.data.resize(frame.data.len(), 0);
+		self.buffer.width = frame.width;
+		self.buffer.height = frame.height;
+
+		// xorshift64
+		self.seed ^= self.seed << 13;
+		self.seed ^= self.seed >> 7;
+		self.seed ^= self.seed << 17;
+
+		let (seed, amount) = (self.seed, self.amount as i32);
+		let range = self.amount as u64 * 2 + 1;
+		let row_len = frame.width * 3;
+
+		bands::in_bands(&mut self.buffer.data, row_len, 1, |first, rows| {
+			let input = frame.data.get(first * row_len..).unwrap_or_default();
+			let first_px = first * frame.width;
+
+			for (idx, (out, px)) in rows
+				.chunks_exact_mut(3)
+				.zip(input.chunks_exact(3))
+				.enumerate()
+			{
+				let grain = (noise(seed, (first_px + idx) as u64) % range) as i32 - amount;
+				for (o, p) in out.iter_mut().zip(px) {
+					*o = (*p as i32 + grain).clamp(0, 255) as u8;
+				}
+			}
+		});
+	}
+
+	fn frame_out(&mut self) -> BorrowedFrame<'_> {
+		self.buffer.borrow()
+	}
+
+	fn params(&self) -> &'static [Param] {
+		Self::PARAMS
+	}
+
+	fn param(&self, name: &str) -> Option<ParamValue> {
+		match name {
+			"amount" => Some(ParamValue::Int(self.amount as i32)),
+			_ => None,
+		}
+	}
+
+	fn set_param(&mut self, name: &str, value: ParamValue) {
+		if name == "amount" {
+			self.amount = value.as_int().clamp(0, 255) as u8;
+		}
+	}
+}
+
+/// Random enough for grain, worked out from the pixel's number so it doesn't
+/// matter what order the pixels are done in. This is splitmix64's mixing.
+fn noise(seed: u64, idx: u64) -> u64 {
+	let mut z = seed.wrapping_add(idx.wrapping_mul(0x9E3779B97F4A7C15));
+	z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+	z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+	z ^ (z >> 31)
+}
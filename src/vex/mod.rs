@@ -1,26 +1,235 @@
-use crate::capture::{BorrowedFrame, Frame};
+use std::collections::VecDeque;
+
+use devout::bands;
+
+use crate::capture::{BorrowedFrame, Effect, Frame, Stage};
+
+mod align;
+mod grain;
+mod param;
+mod pixelate;
+
+pub use align::Align;
+pub use grain::Grain;
+pub use param::{Param, ParamKind, ParamValue};
+pub use pixelate::Pixelate;
 
 pub trait Vex {
 	fn frame_in(&mut self, frame: BorrowedFrame);
-	fn frame_out(&mut self) -> BorrowedFrame;
+	fn frame_out(&mut self) -> BorrowedFrame<'_>;
 
-	fn effect(&mut self, frame: BorrowedFrame) -> BorrowedFrame {
+	fn effect(&mut self, frame: BorrowedFrame) -> BorrowedFrame<'_> {
 		self.frame_in(frame);
 		self.frame_out()
 	}
 
 	/// Takes ownership of the effect and returns a frame to use to prime
 	/// the next effect
-	fn into_frame(self) -> Frame;
+	fn into_frame(self: Box<Self>) -> Frame;
+
+	/// What can be changed about the effect. None by default.
+	fn params(&self) -> &'static [Param] {
+		&[]
+	}
+
+	/// The value of the param called `name`, if there is one.
+	fn param(&self, _name: &str) -> Option<ParamValue> {
+		None
+	}
+
+	/// Change a param. Values should already be clamped with
+	/// [Param::clamp], and names that aren't params are ignored.
+	fn set_param(&mut self, _name: &str, _value: ParamValue) {}
+}
+
+/// The params of `effect`, for when we don't have one to ask.
+pub fn params(effect: Effect) -> &'static [Param] {
+	match effect {
+		Effect::Trichrome => Tricrideo::PARAMS,
+		Effect::Pixelate => Pixelate::PARAMS,
+		Effect::Grain => Grain::PARAMS,
+		Effect::Align => Align::PARAMS,
+	}
+}
+
+/// Make the effect, starting from `frame`. Effects that build up over
+/// several frames, like trichrome, start out looking like it.
+pub fn build(effect: Effect, frame: Frame) -> Box<dyn Vex> {
+	match effect {
+		Effect::Trichrome => Box::new(Tricrideo::from_frame(frame)),
+		Effect::Pixelate => Box::new(Pixelate::from_frame(frame)),
+		Effect::Grain => Box::new(Grain::from_frame(frame)),
+		Effect::Align => Box::new(Align::from_frame(frame)),
+	}
 }
 
+/// Effects one after the other, each one getting what the last one made.
+#[derive(Default)]
+pub struct Pipeline {
+	stages: Vec<(Effect, Box<dyn Vex>)>,
+}
+
+impl Pipeline {
+	/// Change to running `stages`, in that order, with their params. Effects
+	/// we're already running are kept as they are, and new ones are primed with
+	/// the frames of the ones we're getting rid of, or with `shown` if there
+	/// aren't enough of those.
+	pub fn set(&mut self, stages: &[&Stage], shown: BorrowedFrame) {
+		let mut old: Vec<_> = self.stages.drain(..).map(Some).collect();
+
+		let mut kept: Vec<_> = stages
+			.iter()
+			.map(|stage| {
+				let idx = old
+					.iter()
+					.position(|old| matches!(old, Some((e, _)) if *e == stage.effect))?;
+				old[idx].take()
+			})
+			.collect();
+
+		let mut spare = old.into_iter().flatten().map(|(_, vex)| vex.into_frame());
+		for (running, stage) in kept.iter_mut().zip(stages) {
+			if running.is_none() {
+				let frame = spare.next().unwrap_or_else(|| Frame {
+					data: shown.data.to_owned(),
+					width: shown.width,
+					height: shown.height,
+				});
+				*running = Some((stage.effect, build(stage.effect, frame)));
+			}
+		}
+
+		self.stages = kept.into_iter().flatten().collect();
+
+		for ((_, vex), stage) in self.stages.iter_mut().zip(stages) {
+			for (param, value) in vex.params().iter().zip(&stage.params) {
+				if vex.param(param.name) != Some(*value) {
+					vex.set_param(param.name, *value);
+				}
+			}
+		}
+	}
+
+	/// Forget everything, like when the frames change size.
+	pub fn clear(&mut self) {
+		self.stages.clear();
+	}
+
+	/// Put `frame` through every effect. Nothing's copied between them, each
+	/// one reads straight out of the one before.
+	pub fn run<'a>(&'a mut self, frame: BorrowedFrame<'a>) -> BorrowedFrame<'a> {
+		let mut frame = frame;
+		for (_, vex) in self.stages.iter_mut() {
+			frame = vex.effect(frame);
+		}
+		frame
+	}
+}
+
+/// The furthest back a channel can come from, in frames. Every frame in
+/// between is kept, so this is a lot of memory at 1080p.
+const MAX_DELAY: usize = 15;
+
+/// How much each of R, G, and B counts towards the brightness of a pixel in
+/// grey trichrome. Divided by their total.
+const LUMA_WEIGHTS: [[u32; 3]; 6] = [
+	// Average
+	[1, 1, 1],
+	// BT.601
+	[299, 587, 114],
+	// BT.709
+	[2126, 7152, 722],
+	// Just red, green, or blue
+	[1, 0, 0],
+	[0, 1, 0],
+	[0, 0, 1],
+];
+
+/// The orders channels can be updated in, as indices into RGB
+const ORDERS: [[usize; 3]; 6] = [
+	[0, 1, 2],
+	[0, 2, 1],
+	[1, 0, 2],
+	[1, 2, 0],
+	[2, 0, 1],
+	[2, 1, 0],
+];
+
+/// Trichrome, so every channel comes from a different moment.
+///
+/// In colour with no delays, one channel is updated each frame, in turn, so
+/// the channels end up a frame apart. Give them delays, something like 0, 4,
+/// and 8, and every channel is updated every frame from that many frames
+/// back instead, which gets you the big offsets of a real trichrome.
+///
+/// In grey, the newest frame's brightness goes in the first channel of the
+/// order and the last two frames move along to the other two.
 pub struct Tricrideo {
 	buffer: Frame,
 	channel_idx: u8,
 	coloured: bool,
+	/// Which [ORDERS] we go through the channels in
+	order: usize,
+	/// Which [LUMA_WEIGHTS] grey uses
+	luma: usize,
+	/// How many frames back red, green, and blue come from
+	delays: [usize; 3],
+	/// The frames that came in, newest first, as far back as the longest delay
+	history: VecDeque<Vec<u8>>,
 }
 
 impl Tricrideo {
+	pub const PARAMS: &'static [Param] = &[
+		Param {
+			name: "coloured",
+			label: "Coloured",
+			kind: ParamKind::Bool { default: false },
+		},
+		Param {
+			name: "luma",
+			label: "Grey from",
+			kind: ParamKind::Choice {
+				options: &["Average", "BT.601", "BT.709", "Red", "Green", "Blue"],
+				default: 0,
+			},
+		},
+		Param {
+			name: "order",
+			label: "Order",
+			kind: ParamKind::Choice {
+				options: &["RGB", "RBG", "GRB", "GBR", "BRG", "BGR"],
+				default: 0,
+			},
+		},
+		Param {
+			name: "red_delay",
+			label: "Red delay",
+			kind: ParamKind::Int {
+				min: 0,
+				max: MAX_DELAY as i32,
+				default: 0,
+			},
+		},
+		Param {
+			name: "green_delay",
+			label: "Green delay",
+			kind: ParamKind::Int {
+				min: 0,
+				max: MAX_DELAY as i32,
+				default: 0,
+			},
+		},
+		Param {
+			name: "blue_delay",
+			label: "Blue delay",
+			kind: ParamKind::Int {
+				min: 0,
+				max: MAX_DELAY as i32,
+				default: 0,
+			},
+		},
+	];
+
 	pub fn new(width: usize, height: usize) -> Self {
 		Self {
 			buffer: Frame {
@@ -30,6 +239,10 @@ impl Tricrideo {
 			},
 			channel_idx: 0,
 			coloured: false,
+			luma: 0,
+			order: 0,
+			delays: [0; 3],
+			history: VecDeque::new(),
 		}
 	}
 
@@ -38,6 +251,10 @@ impl Tricrideo {
 			buffer: frame,
 			channel_idx: 0,
 			coloured: false,
+			luma: 0,
+			order: 0,
+			delays: [0; 3],
+			history: VecDeque::new(),
 		}
 	}
 
@@ -46,29 +263,87 @@ impl Tricrideo {
 	}
 
 	fn gray(&mut self, frame: BorrowedFrame) {
-		let rgb = frame.data;
+		let [newest, middle, oldest] = ORDERS[self.order];
+		let weights = LUMA_WEIGHTS[self.luma];
+		let total: u32 = weights.iter().sum();
+		let row_len = self.buffer.width * 3;
 
-		for (idx, px) in self.buffer.data.chunks_mut(3).enumerate() {
-			// Do a naïve average of the incoming pixels colour
-			let new = ((rgb[idx * 3] as u32 + rgb[idx * 3 + 1] as u32 + rgb[idx * 3 + 2] as u32)
-				/ 3) as u8;
+		bands::in_bands(&mut self.buffer.data, row_len, 1, |first, rows| {
+			let input = frame.data.get(first * row_len..).unwrap_or_default();
 
-			// Shift colours one forward (R -> G, G -> B) and set out new red
-			px[1] = px[0];
-			px[2] = px[1];
-			px[0] = new;
-		}
+			for (px, rgb) in rows.chunks_mut(3).zip(input.chunks(3)) {
+				let new = (rgb[0] as u32 * weights[0]
+					+ rgb[1] as u32 * weights[1]
+					+ rgb[2] as u32 * weights[2])
+					/ total;
+
+				// Everything moves one frame further back, oldest first so we
+				// don't lose the middle one
+				px[oldest] = px[middle];
+				px[middle] = px[newest];
+				px[newest] = new as u8;
+			}
+		});
 	}
 
 	fn colour(&mut self, frame: BorrowedFrame) {
-		for (idx, px) in self.buffer.data.chunks_mut(3).enumerate() {
-			px[self.channel_idx as usize] = frame.data[idx * 3 + self.channel_idx as usize];
+		self.remember(frame.data);
+
+		// Until we've seen enough frames the oldest we've got will have to do
+		let oldest = self.history.len() - 1;
+		let pasts = self.delays.map(|delay| &self.history[delay.min(oldest)]);
+		let row_len = self.buffer.width * 3;
+
+		if self.delays == [0; 3] {
+			// Nothing's delayed, so the channels take turns and end up a frame
+			// apart
+			let channel = ORDERS[self.order][self.channel_idx as usize];
+			let past = pasts[channel];
+
+			bands::in_bands(&mut self.buffer.data, row_len, 1, |first, rows| {
+				let past = past.get(first * row_len..).unwrap_or_default();
+
+				for (px, past) in rows.chunks_mut(3).zip(past.chunks(3)) {
+					px[channel] = past[channel];
+				}
+			});
+			return;
 		}
+
+		// Otherwise every channel is exactly as far back as its delay says,
+		// every frame
+		bands::in_bands(&mut self.buffer.data, row_len, 1, |first, rows| {
+			let [red, green, blue] =
+				pasts.map(|past| past.get(first * row_len..).unwrap_or_default());
+			let pasts = red.chunks(3).zip(green.chunks(3)).zip(blue.chunks(3));
+
+			for (px, ((red, green), blue)) in rows.chunks_mut(3).zip(pasts) {
+				px[0] = red[0];
+				px[1] = green[1];
+				px[2] = blue[2];
+			}
+		});
+	}
+
+	/// Keep `data` in the history, and forget what's older than we need.
+	fn remember(&mut self, data: &[u8]) {
+		let depth = self.delays.iter().max().copied().unwrap_or(0) + 1;
+
+		// Reuse the oldest frame's buffer if we're full
+		let mut buffer = match self.history.len() >= depth {
+			true => self.history.pop_back().unwrap_or_default(),
+			false => vec![],
+		};
+		buffer.clear();
+		buffer.extend_from_slice(data);
+
+		self.history.push_front(buffer);
+		self.history.truncate(depth);
 	}
 }
 
 impl Vex for Tricrideo {
-	fn into_frame(self) -> Frame {
+	fn into_frame(self: Box<Self>) -> Frame {
 		self.buffer
 	}
 
@@ -82,11 +357,109 @@ impl Vex for Tricrideo {
 		self.channel_idx = (self.channel_idx + 1) % 3;
 	}
 
-	fn frame_out(&mut self) -> BorrowedFrame {
+	fn frame_out(&mut self) -> BorrowedFrame<'_> {
 		BorrowedFrame {
 			data: &self.buffer.data,
 			width: self.buffer.width,
 			height: self.buffer.height,
 		}
 	}
+
+	fn params(&self) -> &'static [Param] {
+		Self::PARAMS
+	}
+
+	fn param(&self, name: &str) -> Option<ParamValue> {
+		match name {
+			"coloured" => Some(ParamValue::Bool(self.coloured)),
+			"luma" => Some(ParamValue::Int(self.luma as i32)),
+			"order" => Some(ParamValue::Int(self.order as i32)),
+			"red_delay" => Some(ParamValue::Int(self.delays[0] as i32)),
+			"green_delay" => Some(ParamValue::Int(self.delays[1] as i32)),
+			"blue_delay" => Some(ParamValue::Int(self.delays[2] as i32)),
+			_ => None,
+		}
+	}
+
+	fn set_param(&mut self, name: &str, value: ParamValue) {
+		let delay = || value.as_int().clamp(0, MAX_DELAY as i32) as usize;
+
+		match name {
+			"coloured" => self.set_coloured(value.as_bool()),
+			"luma" => self.luma = value.as_int().clamp(0, LUMA_WEIGHTS.len() as i32 - 1) as usize,
+			"order" => self.order = value.as_int().clamp(0, ORDERS.len() as i32 - 1) as usize,
+			"red_delay" => self.delays[0] = delay(),
+			"green_delay" => self.delays[1] = delay(),
+			"blue_delay" => self.delays[2] = delay(),
+			_ => (),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Two pixels each, picked so every luma option gives something different
+	#[rustfmt::skip]
+	const FRAMES: [[u8; 6]; 3] = [
+		[200, 100, 50,   0, 0, 255],
+		[10, 20, 30,     255, 255, 255],
+		[90, 180, 45,    60, 0, 120],
+	];
+
+	fn grey(luma: &str, order: &str, frames: &[[u8; 6]]) -> Vec<u8> {
+		let param = |name: &str, value: &str| {
+			let param = Tricrideo::PARAMS.iter().find(|p| p.name == name).unwrap();
+			param.parse(value).unwrap()
+		};
+
+		let mut tri = Tricrideo::new(2, 1);
+		tri.set_param("luma", param("luma", luma));
+		tri.set_param("order", param("order", order));
+
+		for frame in frames {
+			tri.frame_in(BorrowedFrame {
+				data: frame,
+				width: 2,
+				height: 1,
+			});
+		}
+		tri.frame_out().data.to_vec()
+	}
+
+	/// With the default order red is the newest frame, green the one before,
+	/// and blue the one before that.
+	#[test]
+	fn grey_luma_options() {
+		#[rustfmt::skip]
+		let expected: [(&str, [u8; 6]); 6] = [
+			("Average", [105, 20, 116,   60, 255, 85]),
+			("BT.601",  [137, 18, 124,   31, 255, 29]),
+			("BT.709",  [151, 18, 117,   21, 255, 18]),
+			("Red",     [90, 10, 200,    60, 255, 0]),
+			("Green",   [180, 20, 100,   0, 255, 0]),
+			("Blue",    [45, 30, 50,     120, 255, 255]),
+		];
+
+		for (luma, expected) in expected {
+			assert_eq!(grey(luma, "RGB", &FRAMES), expected, "grey from {luma}");
+		}
+	}
+
+	#[test]
+	fn grey_order() {
+		// Newest in blue, then red, then green
+		assert_eq!(grey("Average", "BRG", &FRAMES), [20, 116, 105, 255, 85, 60]);
+	}
+
+	/// The last channel in the order is two frames behind, not the one that
+	/// just came in.
+	#[test]
+	fn oldest_channel_is_two_frames_back() {
+		let frames = [11, 22, 33, 44].map(|red| [red, 0, 0, red, 0, 0]);
+
+		assert_eq!(grey("Red", "RGB", &frames), [44, 33, 22, 44, 33, 22]);
+		assert_eq!(grey("Red", "GBR", &frames), [22, 44, 33, 22, 44, 33]);
+	}
 }
@@ -0,0 +1,151 @@
+use core::fmt;
+
+/// Something about an effect that can be changed, like how big the pixels
+/// are. Effects list theirs in [Vex::params](super::Vex::params) so the GUI
+/// and the command line can offer them without knowing anything about the
+/// effect.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Param {
+	/// Lowercase with no spaces, it's what you type on the command line
+	pub name: &'static str,
+	/// For people to read
+	pub label: &'static str,
+	pub kind: ParamKind,
+}
+
+/// What kind of value a [Param] takes, and what it is when it's not been
+/// changed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParamKind {
+	Bool {
+		default: bool,
+	},
+	Int {
+		min: i32,
+		max: i32,
+		default: i32,
+	},
+	Float {
+		min: f32,
+		max: f32,
+		default: f32,
+	},
+	/// One of a list. The value is an [ParamValue::Int] index into `options`.
+	Choice {
+		options: &'static [&'static str],
+		default: usize,
+	},
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParamValue {
+	Bool(bool),
+	Int(i32),
+	Float(f32),
+}
+
+impl Param {
+	pub fn default(&self) -> ParamValue {
+		match self.kind {
+			ParamKind::Bool { default } => ParamValue::Bool(default),
+			ParamKind::Int { default, .. } => ParamValue::Int(default),
+			ParamKind::Float { default, .. } => ParamValue::Float(default),
+			ParamKind::Choice { default, .. } => ParamValue::Int(default as i32),
+		}
+	}
+
+	/// Make `value` the right kind for this param, and inside its range.
+	pub fn clamp(&self, value: ParamValue) -> ParamValue {
+		match self.kind {
+			ParamKind::Bool { .. } => ParamValue::Bool(value.as_bool()),
+			ParamKind::Int { min, max, .. } => ParamValue::Int(value.as_int().clamp(min, max)),
+			ParamKind::Float { min, max, .. } => {
+				ParamValue::Float(value.as_float().clamp(min, max))
+			}
+			ParamKind::Choice { options, .. } => {
+				ParamValue::Int(value.as_int().clamp(0, options.len() as i32 - 1))
+			}
+		}
+	}
+
+	/// The value for people to read, which is the option's name for choices.
+	pub fn display(&self, value: ParamValue) -> String {
+		match self.kind {
+			ParamKind::Choice { options, .. } => options
+				.get(value.as_int() as usize)
+				.map(|o| o.to_string())
+				.unwrap_or_else(|| value.to_string()),
+			_ => value.to_string(),
+		}
+	}
+
+	/// Read a value from the command line, like `true`, `16`, or `0.5`.
+	pub fn parse(&self, s: &str) -> Result<ParamValue, String> {
+		let value = match self.kind {
+			ParamKind::Bool { .. } => match s {
+				"true" | "yes" | "on" | "1" => ParamValue::Bool(true),
+				"false" | "no" | "off" | "0" => ParamValue::Bool(false),
+				_ => return Err(format!("{} should be true or false, not '{s}'", self.name)),
+			},
+			ParamKind::Int { min, max, .. } => ParamValue::Int(
+				s.parse()
+					.map_err(|_| format!("{} should be a number from {min} to {max}", self.name))?,
+			),
+			ParamKind::Float { min, max, .. } => ParamValue::Float(
+				s.parse()
+					.map_err(|_| format!("{} should be a number from {min} to {max}", self.name))?,
+			),
+			ParamKind::Choice { options, .. } => {
+				let idx = options
+					.iter()
+					.position(|o| o.eq_ignore_ascii_case(s))
+					.ok_or_else(|| {
+						format!(
+							"{} should be one of {}, not '{s}'",
+							self.name,
+							options.join(", ")
+						)
+					})?;
+				ParamValue::Int(idx as i32)
+			}
+		};
+
+		Ok(self.clamp(value))
+	}
+}
+
+impl ParamValue {
+	pub fn as_bool(self) -> bool {
+		match self {
+			ParamValue::Bool(b) => b,
+			ParamValue::Int(i) => i != 0,
+			ParamValue::Float(f) => f != 0.0,
+		}
+	}
+
+	pub fn as_int(self) -> i32 {
+		match self {
+			ParamValue::Bool(b) => b as i32,
+			ParamValue::Int(i) => i,
+			ParamValue::Float(f) => f.round() as i32,
+		}
+	}
+
+	pub fn as_float(self) -> f32 {
+		match self {
+			ParamValue::Bool(b) => b as i32 as f32,
+			ParamValue::Int(i) => i as f32,
+			ParamValue::Float(f) => f,
+		}
+	}
+}
+
+impl fmt::Display for ParamValue {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ParamValue::Bool(b) => write!(f, "{b}"),
+			ParamValue::Int(i) => write!(f, "{i}"),
+			ParamValue::Float(v) => write!(f, "{v}"),
+		}
+	}
+}
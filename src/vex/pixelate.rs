@@ -0,0 +1,101 @@
+use devout::bands;
+
+use super::{Param, ParamKind, ParamValue, Vex};
+use crate::capture::{BorrowedFrame, Frame};
+
+/// Big square pixels, each the average of what's under it.
+pub struct Pixelate {
+	buffer: Frame,
+	/// How many pixels wide the squares are
+	size: usize,
+}
+
+impl Pixelate {
+	pub const PARAMS: &'static [Param] = &[Param {
+		name: "size",
+		label: "Size",
+		kind: ParamKind::Int {
+			min: 2,
+			max: 64,
+			default: 8,
+		},
+	}];
+
+	pub fn from_frame(frame: Frame) -> Self {
+		Self {
+			buffer: frame,
+			size: Self::PARAMS[0].default().as_int() as usize,
+		}
+	}
+}
+
+impl Vex for Pixelate {
+	fn into_frame(self: Box<Self>) -> Frame {
+		self.buffer
+	}
+
+	fn frame_in(&mut self, frame: BorrowedFrame) {
+		let (width, height) = (frame.width, frame.height);
+		self.buffer.data.resize(frame.data.len(), 0);
+		self.buffer.width = width;
+		self.buffer.height = height;
+		let size = self.size;
+
+		// Bands are whole rows of squares, so no square is split between two
+		bands::in_bands(&mut self.buffer.data, width * 3, size, |first, out| {
+			let band_height = out.len() / (width * 3);
+
+			for by in (0..band_height).step_by(size) {
+				let rows = by..(by + size).min(band_height);
+
+				for bx in (0..width).step_by(size) {
+					let cols = bx..(bx + size).min(width);
+
+					let mut sum = [0u32; 3];
+					for y in rows.clone() {
+						let y = first + y;
+						let row =
+							&frame.data[(y * width + cols.start) * 3..(y * width + cols.end) * 3];
+						for px in row.chunks_exact(3) {
+							sum[0] += px[0] as u32;
+							sum[1] += px[1] as u32;
+							sum[2] += px[2] as u32;
+						}
+					}
+
+					let count = (rows.len() * cols.len()) as u32;
+					let average = sum.map(|s| (s / count) as u8);
+
+					for y in rows.clone() {
+						let row =
+							&mut out[(y * width + cols.start) * 3..(y * width + cols.end) * 3];
+						for px in row.chunks_exact_mut(3) {
+							px.copy_from_slice(&average);
+						}
+					}
+				}
+			}
+		});
+	}
+
+	fn frame_out(&mut self) -> BorrowedFrame<'_> {
+		self.buffer.borrow()
+	}
+
+	fn params(&self) -> &'static [Param] {
+		Self::PARAMS
+	}
+
+	fn param(&self, name: &str) -> Option<ParamValue> {
+		match name {
+			"size" => Some(ParamValue::Int(self.size as i32)),
+			_ => None,
+		}
+	}
+
+	fn set_param(&mut self, name: &str, value: ParamValue) {
+		if name == "size" {
+			self.size = value.as_int().max(1) as usize;
+		}
+	}
+}